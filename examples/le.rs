@@ -111,7 +111,7 @@ fn main() {
     ];
 
     let res = solve_minimum(start, infer);
-    for i in 0..res.len() {
-        println!("{:?}", res[i]);
+    for fact in &res {
+        println!("{:?}", fact);
     }
 }
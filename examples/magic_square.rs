@@ -10,7 +10,7 @@ In this example, we will prove the following solutions of the 3x3 magic square:
 
 extern crate linear_solver;
 
-use linear_solver::{solve_minimum, Inference};
+use linear_solver::{solve_parallel, Inference, Rules, SolveOptions};
 use linear_solver::Inference::*;
 
 use std::collections::HashSet;
@@ -71,7 +71,7 @@ impl Expr {
                 if let Var(a) = ls[0] {
                     if rs.len() == 1 {
                         if let Const(x) = rs[0] {return Some((a, x))}
-                    } else if rs.len() == 0 {return Some((a, 0))}
+                    } else if rs.is_empty() {return Some((a, 0))}
                 }
             }
         }
@@ -150,10 +150,8 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
             for i in 0..vars.len() {
                 let var = vars[i];
                 for j in 0..vars.len() {
-                    if vars[j] != var {
-                        if rss[j] == rss[i] {
-                            return Some(Propagate(False));
-                        }
+                    if vars[j] != var && rss[j] == rss[i] {
+                        return Some(Propagate(False));
                     }
                 }
             }
@@ -183,7 +181,7 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
 
             if cache.contains(&CheckContradictingConstants) {
                 if let Sum(ref ls, ref rs) = *ea {
-                    if rs.len() == 0 && ls.len() == 1 {
+                    if rs.is_empty() && ls.len() == 1 {
                         if let Const(x) = ls[0] {
                             if x != 0 {
                                 return Some(Propagate(False));
@@ -195,7 +193,7 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
 
             if cache.contains(&AbsoluteNumbers) {
                 if let Sum(ref ls, ref rs) = *ea {
-                    if rs.len() == 0 && ls.len() == 2 {
+                    if rs.is_empty() && ls.len() == 2 {
                         if let Const(x) = ls[0] {
                             if let Var(_) = ls[1] {
                                 if x != 0 {
@@ -211,17 +209,17 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
                 if let Sum(ref ls, ref rs) = *ea {
                     let mut sum = 0;
                     let mut count = 0;
-                    for i in 0..ls.len() {
-                        if let Const(x) = ls[i] {
+                    for l in ls {
+                        if let Const(x) = *l {
                             sum += x;
                             count += 1;
                         }
                     }
                     if count > 1 {
                         let mut new_ls = vec![];
-                        for i in 0..ls.len() {
-                            if let Const(_) = ls[i] {continue}
-                            new_ls.push(ls[i].clone());
+                        for l in ls {
+                            if let Const(_) = *l {continue}
+                            new_ls.push(l.clone());
                         }
                         new_ls.push(Const(sum));
                         return Some(Inference::replace_one(
@@ -239,12 +237,12 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
                         for j in 0..rs.len() {
                             if ls[i] == rs[j] {
                                 let mut new_ls = vec![];
-                                for k in 0..ls.len() {
-                                    if k == i {continue} else {new_ls.push(ls[k].clone())}
+                                for (k, l) in ls.iter().enumerate() {
+                                    if k == i {continue} else {new_ls.push(l.clone())}
                                 }
                                 let mut new_rs = vec![];
-                                for k in 0..rs.len() {
-                                    if k == j {continue} else {new_rs.push(rs[k].clone())}
+                                for (k, r) in rs.iter().enumerate() {
+                                    if k == j {continue} else {new_rs.push(r.clone())}
                                 }
                                 return Some(Inference::replace_one(
                                     ea.clone(),
@@ -265,11 +263,11 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
                             for eb in facts {
                                 if ea == eb {continue};
                                 if let Sum(ref ls2, ref rs2) = *eb {
-                                    for i in 0..ls2.len() {
-                                        if ls2[i] == ls[0] {
+                                    for l2 in ls2 {
+                                        if l2 == &ls[0] {
                                             let new_ls: Vec<Expr> = ls2.clone().into_iter()
                                                 .filter(|n| n != &ls[0])
-                                                .chain(rs.clone().into_iter())
+                                                .chain(rs.clone())
                                                 .collect();
                                             return Some(Inference::replace_one(
                                                 eb.clone(),
@@ -292,21 +290,21 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
                         for j in 0..rs.len() {
                             if let (&Const(x), &Const(y)) = (&ls[i], &rs[j]) {
                                 let mut new_ls = vec![];
-                                for k in 0..ls.len() {
+                                for (k, l) in ls.iter().enumerate() {
                                     if k == i {
                                         if x == y {continue}
                                         else if x > y {new_ls.push(Const(x-y))}
                                     } else {
-                                        new_ls.push(ls[k].clone())
+                                        new_ls.push(l.clone())
                                     }
                                 }
                                 let mut new_rs = vec![];
-                                for k in 0..rs.len() {
+                                for (k, r) in rs.iter().enumerate() {
                                     if k == j {
                                         if x == y {continue}
                                         else if y > x {new_rs.push(Const(y-x))}
                                     } else {
-                                        new_rs.push(rs[k].clone())
+                                        new_rs.push(r.clone())
                                     }
                                 }
                                 return Some(Inference::replace_one(
@@ -362,7 +360,7 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
                     cache
                 ));
             }
-            if alternatives.len() == 0 {
+            if alternatives.is_empty() {
                 return Some(Propagate(False));
             }
         }
@@ -372,23 +370,11 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
                 if let Range {var, start, end} = *eb {
                     if var == a {
                         // Try the whole range.
-                        // Call the solver recursively
-                        let mut alternatives = vec![];
-                        for k in start..end+1 {
-                            let mut new_facts = vec![];
-                            for i in 0..facts.len() {
-                                // Ignore `Narrow` directive to avoid infinite recursion.
-                                if &facts[i] == ea {continue};
-                                new_facts.push(facts[i].clone());
-                            }
-                            new_facts.push(Sum(vec![Var(var)], vec![Const(k)]));
-                            let res = solve_minimum(new_facts, infer);
-                            if !res.iter().any(|n| n == &False) {
-                                alternatives.push(k);
-                            }
-                        }
-                        let new_expr = Alternatives(a, alternatives);
-                        return Some(SimplifyOne {from: ea.clone(), to: new_expr});
+                        // The solver solves every case recursively without `Narrow`.
+                        return Some(Split {
+                            from: ea.clone(),
+                            cases: (start..end+1).map(|k| Sum(vec![Var(var)], vec![Const(k)])).collect(),
+                        });
                     }
                 }
             }
@@ -398,6 +384,23 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
     None
 }
 
+/// Collects alternatives that do not lead to `False` when narrowing.
+#[derive(Clone)]
+pub struct MagicSquare;
+
+impl Rules<Expr> for MagicSquare {
+    fn infer(&mut self, cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
+        infer(cache, facts)
+    }
+
+    fn split(&mut self, from: &Expr, survivors: Vec<Expr>) -> Vec<Expr> {
+        if let Narrow(a) = *from {
+            let alternatives = survivors.iter().filter_map(|n| n.assignment()).map(|(_, x)| x).collect();
+            vec![Alternatives(a, alternatives)]
+        } else {survivors}
+    }
+}
+
 fn main() {
     let start = vec![
         // a + b + c = 15
@@ -497,8 +500,10 @@ fn main() {
         */
     ];
 
-    let res = solve_minimum(start, infer);
-    for i in 0..res.len() {
-        println!("{:?}", res[i]);
+    let options = SolveOptions {contradiction: Some(|n| n == &False), ..Default::default()};
+    // Narrow down alternatives on multiple threads.
+    let res = solve_parallel(start, &mut MagicSquare, &options).into_facts();
+    for fact in &res {
+        println!("{:?}", fact);
     }
 }
@@ -31,14 +31,11 @@ pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) -> Option<Inference<Expr>> {
             Prime(i) => {
                 for (ind2, eb) in facts.iter().enumerate() {
                     if ind == ind2 {continue};
-                    match *eb {
-                        Prime(j) => {
-                            if i % j == 0 {
-                                // Remove the number.
-                                return Some(OneTrue {from: ea.clone()});
-                            }
+                    if let Prime(j) = *eb {
+                        if i % j == 0 {
+                            // Remove the number.
+                            return Some(OneTrue {from: ea.clone()});
                         }
-                        _ => {}
                     }
                 }
             }
@@ -62,7 +59,7 @@ fn main() {
     let start = vec![Upto(100)];
 
     let res = solve_minimum(start, infer);
-    for i in 0..res.len() {
-        println!("{:?}", res[i]);
+    for fact in &res {
+        println!("{:?}", fact);
     }
 }
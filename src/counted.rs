@@ -0,0 +1,99 @@
+//! Solving with every distinct fact stored once with its number of copies.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use super::{Database, Rules, SolveOptions, SolveOutcome, Solver, StateIdentity};
+
+/// Solves like `solve`, but stores every distinct fact once with its number of copies.
+///
+/// This is faster than `solve` when there are many copies of the same facts,
+/// e.g. thousands of linear resources.
+/// Consuming or producing a copy and comparing sets of facts for cycle detection
+/// take time in the number of distinct facts, not in the number of copies,
+/// and `Inference::Counted` consumes or produces many copies in one step.
+///
+/// Rules see every distinct fact once in `Context::facts`,
+/// and use `Context::count` to get the number of copies of a fact.
+/// `Inference::PropagateOnce` fires once for the same distinct facts, not for every copy.
+/// Rules that only query the facts through the cache and `Context::count`
+/// see the same information as with `solve`.
+///
+/// The returned facts contain every copy, where copies of a fact are next to each other.
+/// With `SolveOptions::measure`, the facts are expanded the same way
+/// to compare them while searching the minimum set of facts in a cycle.
+pub fn solve_counted<T, R>(facts: Vec<T>, rules: &mut R, options: &SolveOptions<T>) -> SolveOutcome<T>
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized
+{
+    Solver::with_db(Database::counted(facts), options.clone()).run(rules)
+}
+
+// Stores the number of copies of every distinct fact.
+#[derive(Clone)]
+pub(crate) struct Counted<T> {
+    // The number of copies of the fact at the same position.
+    pub(crate) copies: Vec<usize>,
+    // The position of every distinct fact.
+    pub(crate) positions: HashMap<T, usize>,
+    // The total number of copies.
+    pub(crate) len: usize,
+}
+
+impl<T: Clone + Eq + Hash> Counted<T> {
+    pub(crate) fn new(facts: &[T], copies: Vec<usize>) -> Counted<T> {
+        let positions = facts.iter().cloned().enumerate().map(|(i, fact)| (fact, i)).collect();
+        let len = copies.iter().sum();
+        Counted {copies, positions, len}
+    }
+
+    // Returns a hash of the facts and their copies which respects the state identity.
+    pub(crate) fn state_hash(&self, facts: &[T], identity: StateIdentity) -> u64 {
+        fn hash<U: Hash + ?Sized>(x: &U) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            x.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        match identity {
+            StateIdentity::Ordered => hash(&(facts, &self.copies)),
+            StateIdentity::Multiset => {
+                // Adding hashes of facts is independent of order.
+                let sum = facts.iter().zip(&self.copies)
+                    .fold(0u64, |sum, pair| sum.wrapping_add(hash(&pair)));
+                hash(&(facts.len(), sum))
+            }
+        }
+    }
+}
+
+// Returns every copy of the facts, where copies of a fact are next to each other.
+// Facts without copies are already expanded.
+pub(crate) fn expand<'a, T: Clone>(facts: &'a [T], copies: &[usize]) -> Cow<'a, [T]> {
+    if copies.is_empty() {return Cow::Borrowed(facts)};
+    let mut expanded = Vec::with_capacity(copies.iter().sum());
+    for (fact, &n) in facts.iter().zip(copies) {
+        expanded.extend(std::iter::repeat(fact).take(n).cloned());
+    }
+    Cow::Owned(expanded)
+}
+
+// Returns `true` if the facts `a` with their copies are simpler than `b`.
+// Copies are empty when the facts are not counted.
+pub(crate) fn simpler_counted<T: Clone + Hash>(
+    options: &SolveOptions<T>,
+    a: &[T],
+    a_copies: &[usize],
+    b: &[T],
+    b_copies: &[usize]
+) -> bool {
+    fn len<T>(facts: &[T], copies: &[usize]) -> usize {
+        if copies.is_empty() {facts.len()} else {copies.iter().sum()}
+    }
+
+    match options.measure {
+        None => len(a, a_copies) < len(b, b_copies),
+        Some(_) => options.simpler(&expand(a, a_copies), &expand(b, b_copies)),
+    }
+}
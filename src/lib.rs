@@ -10,10 +10,14 @@
 //! If you are looking for a solver that does not remove facts,
 //! see [monotonic_solver](https://github.com/advancedresearch/monotonic_solver)
 //!
-//! *Notice! This solver does not support multiple histories.
+//! *Notice! `solve_minimum` does not support multiple histories.
 //! It assumes that when facts are simplified,
 //! they prove the same set of facts without the simplifaction.*
 //!
+//! To explore alternative inferences, e.g. for planning,
+//! implement `Rules::infer_all` or use `Choices`, then call `solve_all` or `solve_goal`.
+//! Every alternative starts a separate history.
+//!
 //! A linear solver can be used to:
 //!
 //! - Prove some things in linear logic
@@ -25,6 +29,8 @@
 //! This project was heavily inspired by
 //! [CHR (Constraint Handling Rules)](https://dtai.cs.kuleuven.be/CHR/)
 //!
+//! The `rules!` macro generates an `infer` function from rules written in CHR style.
+//!
 //! ### Example: Walk
 //!
 //! ```rust
@@ -207,6 +213,29 @@
 //! }
 //! ```
 //!
+//! ### Rules with state
+//!
+//! The `solve_minimum` function accepts a plain function pointer.
+//! Use `solve_minimum_with` to pass a closure or any type implementing `Rules`,
+//! e.g. when rules need configuration, lookup tables or counters:
+//!
+//! ```rust
+//! use linear_solver::{solve_minimum_with, Inference};
+//!
+//! use std::collections::HashSet;
+//!
+//! let forbidden = 3;
+//! let mut removed = 0;
+//! let res = solve_minimum_with(vec![1, 2, 3], &mut |cache: &HashSet<u32>, _facts: &[u32]| {
+//!     if cache.contains(&forbidden) {
+//!         removed += 1;
+//!         Some(Inference::OneTrue {from: forbidden})
+//!     } else {None}
+//! });
+//! assert_eq!(res, vec![1, 2]);
+//! assert_eq!(removed, 1);
+//! ```
+//!
 //! ### Linear logic
 //!
 //! When some facts are simplified, e.g.:
@@ -226,6 +255,41 @@
 //! However, when doing linear theorem proving,
 //! one can generate redundant facts `Z` for every `X` and `Y`.
 //!
+//! The `cache` only tells whether a fact exists.
+//! Rules that depend on the number of copies, e.g. "two `Left` cancel one `Right`",
+//! can use `Context::count` by overriding `Rules::infer_with` or wrapping a closure in `WithContext`.
+//!
+//! Propagation rules can return `Inference::PropagateOnce` instead of checking the `cache`.
+//! The solver then keeps a propagation history, such that a rule fires once for the same facts.
+//!
+//! With many copies of the same facts, e.g. thousands of linear resources,
+//! use `solve_counted`, which stores every distinct fact once with its number of copies.
+//! Rules then see every distinct fact once and use `Context::count`,
+//! and can return `Inference::Counted` to consume or produce many copies at once.
+//!
+//! ### Sessions
+//!
+//! When facts are added over time, create a `Solver` session and call `Solver::add_facts`,
+//! `Solver::retract` and `Solver::run`. Each run continues from the last reached state.
+//! To apply one inference at a time, e.g. for animations or custom stopping criteria,
+//! iterate over `Solver::iter_steps`.
+//!
+//! ### Scheduling rules
+//!
+//! To name rules, collect them in a `RuleSet` with a `RuleKind` and a priority.
+//! Simplification rules are tried before propagation rules,
+//! and the name of the rule that fired is recorded in `TraceStep::rule` and `Step::rule`.
+//!
+//! Instead of facts that tell which rules should fire, e.g. `SortAll`,
+//! rules can be grouped under tactic names in `Tactics`.
+//! Tactics are enabled or disabled without changing the facts,
+//! and `Tactics::sequence` runs stages of tactics one after another.
+//!
+//! To run rules in phases, e.g. expanding equations before simplifying them,
+//! use `solve_minimum_phases` or `solve_phases`.
+//! Each phase runs until no rule applies or a cycle is reached,
+//! and cycle detection starts over for the next phase.
+//!
 //! ### Meaning of goals
 //!
 //! Since a linear solver can both introduce new facts
@@ -236,6 +300,9 @@
 //! This is the repeated list of sets of facts that follows from
 //! using a deterministic solver with rules that stops expanding.
 //!
+//! Use `solve_cycle` to get every set of facts in the cycle,
+//! or `prove` to check whether a goal belongs to it.
+//!
 //! The minimum set of facts in the cycle is considered the implicit goal,
 //! because all the other facts in the cycle can be inferred from
 //! this set of facts.
@@ -249,6 +316,18 @@
 //! Both the minimum set of facts and the minimum set of axioms can be used
 //! to identify an equivalence between two sets of facts.
 //!
+//! ### Termination
+//!
+//! `solve_minimum` returns the same kind of result for a fixpoint and a cycle.
+//! Use `solve_minimum_report` to get the `Termination`,
+//! the number of inference steps and the length of the cycle.
+//!
+//! By default, the minimum set of facts in a cycle is the first one with the least amount of facts.
+//! To use another notion of simplest, e.g. the total size of terms,
+//! set `SolveOptions::measure` to a `Measure` with a cost function.
+//! Sets of facts with the same cost are then ordered by a hash of the state.
+//! The measure also orders `Search::BestFirst` and `Cycle::minimum`.
+//!
 //! ### Intuition of `false` and `true`
 //!
 //! The intuition of `false` can be thought of as:
@@ -272,15 +351,46 @@
 //! Therefore a proof from initial facts is `true`
 //! if it's minimum set of facts does not equals `false`.
 //!
+//! Set `SolveOptions::contradiction` to tell the solver which facts mean `false`.
+//! The solver then stops with `SolveOutcome::Contradiction` as soon as one is produced,
+//! such that rules do not need to check for `false` themselves.
+//!
+//! To narrow down the values of a variable, a rule can return `Inference::Split` with one case per value.
+//! The solver then proves every case separately and keeps the cases that do not lead to `false`.
+//! Use `solve_parallel` to prove the cases on multiple threads.
+//! It clones the rules for every thread, so rule sets and tactics for it are created with `RuleSet::shared` and `Tactics::shared`.
+//!
 
 extern crate cuckoofilter;
 
 use cuckoofilter::CuckooFilter;
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::DefaultHasher;
-use std::hash::Hash;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::Instant;
+
+#[macro_use]
+mod macros;
+mod counted;
+mod parallel;
+mod rule_set;
+mod search;
+mod tactics;
+
+pub use counted::solve_counted;
+pub use parallel::{solve_branches, solve_parallel, SharedRules};
+pub use rule_set::{RuleKind, RuleSet};
+pub use search::{solve_all, solve_goal};
+pub use tactics::Tactics;
+
+use counted::{expand, simpler_counted, Counted};
 
 /// Tells the solver how to treat inference.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Inference<T> {
     /// Consumes `from` while producing nothing.
     OneTrue {
@@ -315,6 +425,78 @@ pub enum Inference<T> {
     },
     /// Add new fact.
     Propagate(T),
+    /// Keeps `keep`, consumes `remove` and adds `add`.
+    ///
+    /// Nothing happens unless all facts in `keep` and `remove` exist.
+    /// A fact listed in both needs a copy for each.
+    Simpagation {
+        /// Facts that must exist, but are not removed.
+        keep: Vec<T>,
+        /// Facts to remove from context.
+        remove: Vec<T>,
+        /// Facts to be added to context.
+        add: Vec<T>,
+    },
+    /// Add new facts unless the rule already fired for the same heads.
+    ///
+    /// The solver keeps a propagation history of rules and fact instances,
+    /// such that a propagation fires at most once for the same facts.
+    /// Copies of a fact are different instances,
+    /// so this can be used when duplicate facts are legitimate,
+    /// except with `solve_counted`, where copies share one instance.
+    /// Use `Context::fired` to find heads for which the rule did not fire yet.
+    PropagateOnce {
+        /// A number identifying the rule.
+        rule: usize,
+        /// Indices of the matched facts.
+        heads: Vec<usize>,
+        /// Facts to add.
+        to: Vec<T>,
+    },
+    /// Consumes and produces many copies of facts at once.
+    ///
+    /// Each fact is paired with the number of copies.
+    /// This is efficient with `solve_counted`.
+    Counted {
+        /// Facts to remove from context with the number of copies.
+        from: Vec<(T, usize)>,
+        /// Facts to be added to context with the number of copies.
+        to: Vec<(T, usize)>,
+    },
+    /// Solves a branch for every case and replaces `from` with the cases that survived.
+    ///
+    /// Each branch starts with the current facts without `from`, plus the case.
+    /// A branch survives unless it ends in a contradiction, see `SolveOptions::contradiction`.
+    /// Override `Rules::split` to choose the facts that replace `from`.
+    /// When no case survives, the split is a contradiction,
+    /// and the solver stops with `SolveOutcome::Contradiction`.
+    /// The result of every branch is remembered while solving,
+    /// such that the same branch is not solved twice.
+    Split {
+        /// Fact requesting the split.
+        from: T,
+        /// Facts to try, one for each branch.
+        cases: Vec<T>,
+    },
+}
+
+impl<T> Inference<T> {
+    // Returns facts that are consumed or kept, with the number of copies.
+    fn required(&self) -> Vec<(&T, usize)> {
+        match *self {
+            Inference::ManyTrue {ref from} |
+            Inference::Simplify {ref from, ..} |
+            Inference::SimplifyMany {ref from, ..} => from.iter().map(|n| (n, 1)).collect(),
+            Inference::OneTrue {ref from} |
+            Inference::SimplifyOne {ref from, ..} |
+            Inference::Split {ref from, ..} => vec![(from, 1)],
+            Inference::Simpagation {ref keep, ref remove, ..} =>
+                keep.iter().chain(remove.iter()).map(|n| (n, 1)).collect(),
+            Inference::Propagate(_) |
+            Inference::PropagateOnce {..} => vec![],
+            Inference::Counted {ref from, ..} => from.iter().map(|&(ref n, k)| (n, k)).collect(),
+        }
+    }
 }
 
 impl<T: Eq + Hash> Inference<T> {
@@ -355,136 +537,2530 @@ impl<T: Eq + Hash> Inference<T> {
     }
 }
 
-enum State<T> {
-    // Infer new facts.
-    Solving,
-    // Go to a state where the least amount of facts were present.
-    SearchMinimum(Vec<T>),
+/// Gives rules access to the state of the solver.
+pub struct Context<'a, T: 'a> {
+    cache: &'a HashSet<T>,
+    counts: &'a HashMap<T, usize>,
+    facts: &'a [T],
+    ids: &'a [usize],
+    history: &'a HashSet<(usize, Vec<usize>)>,
 }
 
-/// Solves the starting condition using the `infer` function for inference.
+impl<'a, T: Eq + Hash> Context<'a, T> {
+    /// Returns the set of unique facts.
+    pub fn cache(&self) -> &'a HashSet<T> {self.cache}
+
+    /// Returns the list of facts, including copies.
+    ///
+    /// With `solve_counted`, every distinct fact is listed once.
+    pub fn facts(&self) -> &'a [T] {self.facts}
+
+    /// Returns `true` if there is at least one copy of a fact.
+    pub fn contains(&self, fact: &T) -> bool {self.cache.contains(fact)}
+
+    /// Returns the number of copies of a fact.
+    pub fn count(&self, fact: &T) -> usize {
+        self.counts.get(fact).cloned().unwrap_or(0)
+    }
+
+    /// Returns a number identifying the fact instance at an index.
+    ///
+    /// A fact keeps its identity until it is removed.
+    pub fn id(&self, index: usize) -> usize {self.ids[index]}
+
+    /// Returns `true` if the rule already fired for facts at the indices.
+    ///
+    /// See `Inference::PropagateOnce`.
+    pub fn fired(&self, rule: usize, heads: &[usize]) -> bool {
+        let ids = heads.iter().map(|&i| self.ids[i]).collect();
+        self.history.contains(&(rule, ids))
+    }
+}
+
+/// Implemented by rules used for inference.
 ///
-/// Assumes that `infer` is deterministic and leading to a cycle for every input.
-/// Finds the minimum set of facts in the cycle.
-pub fn solve_minimum<T: Clone + PartialEq + Eq + Hash>(
-    mut facts: Vec<T>,
-    infer: fn(cache: &HashSet<T>, &[T]) -> Option<Inference<T>>
-) -> Vec<T> {
-    fn remove_from<T: Eq + Hash>(from: &[T], cache: &mut HashSet<T>, facts: &mut Vec<T>) {
-        for new_fact in from {
-            let mut unique = false;
-            let mut i = 0;
-            loop {
-                if i >= facts.len() {break};
-                if new_fact == &facts[i] {
-                    if unique {
-                        unique = false;
-                        break;
-                    }
-                    // Since using swap remove,
-                    // should check the same index twice.
+/// This is implemented for all closures and functions with the signature
+/// `FnMut(&HashSet<T>, &[T]) -> Option<Inference<T>>`.
+/// Implement it for your own type when rules need to carry state,
+/// e.g. configuration, lookup tables or counters.
+///
+/// Only `infer` is required, the other methods have default implementations.
+pub trait Rules<T> {
+    /// Infers a new step from the current facts.
+    ///
+    /// Returns `None` when no rule applies.
+    fn infer(&mut self, cache: &HashSet<T>, facts: &[T]) -> Option<Inference<T>>;
+
+    /// Infers a new step from the solver context.
+    ///
+    /// This is called by the solver and calls `infer` by default.
+    /// Override it to know how many copies there are of each fact, see `Context::count`.
+    fn infer_with(&mut self, ctx: &Context<T>) -> Option<Inference<T>> {
+        self.infer(ctx.cache, ctx.facts)
+    }
+
+    /// Infers alternative steps, each starting a separate history.
+    ///
+    /// This is called by `solve_all` and `solve_goal`,
+    /// and returns the inference of `infer_with` by default.
+    /// Returns an empty list when no rule applies.
+    fn infer_all(&mut self, ctx: &Context<T>) -> Vec<Inference<T>> {
+        self.infer_with(ctx).into_iter().collect()
+    }
+
+    /// Called when the solver starts inferring from a list of facts.
+    ///
+    /// Override this together with `added` and `removed`
+    /// to keep data structures in sync with the facts, e.g. an `Index`.
+    /// When exploring multiple histories, this is called before every `infer_all`.
+    fn reset(&mut self, _facts: &[T]) {}
+
+    /// Called when a fact is added.
+    fn added(&mut self, _fact: &T) {}
+
+    /// Called when a fact is removed.
+    fn removed(&mut self, _fact: &T) {}
+
+    /// Called with the cases of `Inference::Split` that survived.
+    ///
+    /// This is not called when no case survived, since the split is then a contradiction.
+    /// Returns the facts that replace `from`, which are the surviving cases by default.
+    fn split(&mut self, _from: &T, survivors: Vec<T>) -> Vec<T> {survivors}
+
+    /// Returns the name of the rule that returned the last inference.
+    ///
+    /// The solver uses this to report which rule fired, e.g. in a `Trace`.
+    fn fired_rule(&self) -> Option<&str> {None}
+}
+
+impl<T, F> Rules<T> for F
+    where F: FnMut(&HashSet<T>, &[T]) -> Option<Inference<T>>
+{
+    fn infer(&mut self, cache: &HashSet<T>, facts: &[T]) -> Option<Inference<T>> {
+        self(cache, facts)
+    }
+}
+
+// Infers with a context built from the facts alone,
+// for rules that override `Rules::infer_with`.
+fn infer_from_facts<T, R>(rules: &mut R, cache: &HashSet<T>, facts: &[T]) -> Option<Inference<T>>
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized
+{
+    let mut counts = HashMap::new();
+    for fact in facts {
+        *counts.entry(fact.clone()).or_insert(0) += 1;
+    }
+    let ids: Vec<usize> = (0..facts.len()).collect();
+    let history = HashSet::new();
+    rules.infer_with(&Context {cache, counts: &counts, facts, ids: &ids, history: &history})
+}
+
+/// Wraps a closure that infers from the solver context.
+///
+/// Use this instead of implementing `Rules::infer_with` for your own type.
+#[derive(Clone)]
+pub struct WithContext<F>(pub F);
+
+impl<F> WithContext<F> {
+    /// Creates new rules from a closure.
+    ///
+    /// This helps the compiler infer the argument type of the closure.
+    pub fn new<T>(f: F) -> WithContext<F>
+        where F: FnMut(&Context<T>) -> Option<Inference<T>>
+    {
+        WithContext(f)
+    }
+}
+
+impl<T, F> Rules<T> for WithContext<F>
+    where T: Clone + Eq + Hash, F: FnMut(&Context<T>) -> Option<Inference<T>>
+{
+    fn infer(&mut self, cache: &HashSet<T>, facts: &[T]) -> Option<Inference<T>> {
+        infer_from_facts(self, cache, facts)
+    }
+
+    fn infer_with(&mut self, ctx: &Context<T>) -> Option<Inference<T>> {
+        (self.0)(ctx)
+    }
+}
+
+/// Wraps a closure that infers alternatives from the solver context.
+///
+/// See `Rules::infer_all`.
+#[derive(Clone)]
+pub struct Choices<F>(pub F);
+
+impl<F> Choices<F> {
+    /// Creates new rules from a closure.
+    ///
+    /// This helps the compiler infer the argument type of the closure.
+    pub fn new<T>(f: F) -> Choices<F>
+        where F: FnMut(&Context<T>) -> Vec<Inference<T>>
+    {
+        Choices(f)
+    }
+}
+
+impl<T, F> Rules<T> for Choices<F>
+    where T: Clone + Eq + Hash, F: FnMut(&Context<T>) -> Vec<Inference<T>>
+{
+    fn infer(&mut self, cache: &HashSet<T>, facts: &[T]) -> Option<Inference<T>> {
+        infer_from_facts(self, cache, facts)
+    }
+
+    fn infer_with(&mut self, ctx: &Context<T>) -> Option<Inference<T>> {
+        (self.0)(ctx).into_iter().next()
+    }
+
+    fn infer_all(&mut self, ctx: &Context<T>) -> Vec<Inference<T>> {(self.0)(ctx)}
+}
+
+/// Stores facts by a key, such that rules can find matching facts quickly.
+///
+/// For example, facts can be indexed by enum discriminant or by some field,
+/// which avoids scanning all facts for every pattern.
+/// Facts without a key are not stored.
+#[derive(Clone)]
+pub struct Index<K, T> {
+    key: fn(&T) -> Option<K>,
+    map: HashMap<K, Vec<T>>,
+}
+
+impl<K: Eq + Hash, T: Clone + PartialEq> Index<K, T> {
+    /// Creates a new empty index using `key` to look up facts.
+    pub fn new(key: fn(&T) -> Option<K>) -> Index<K, T> {
+        Index {key, map: HashMap::new()}
+    }
+
+    /// Returns all copies of facts with the key.
+    pub fn get(&self, key: &K) -> &[T] {
+        self.map.get(key).map(|facts| &facts[..]).unwrap_or(&[])
+    }
+
+    /// Adds a copy of a fact.
+    pub fn insert(&mut self, fact: &T) {
+        if let Some(key) = (self.key)(fact) {
+            self.map.entry(key).or_default().push(fact.clone());
+        }
+    }
+
+    /// Removes a copy of a fact.
+    pub fn remove(&mut self, fact: &T) {
+        if let Some(key) = (self.key)(fact) {
+            if let Some(facts) = self.map.get_mut(&key) {
+                if let Some(i) = facts.iter().position(|n| n == fact) {
                     facts.swap_remove(i);
-                    unique = true;
-                } else {
-                    i += 1;
                 }
-            }
-            if unique {
-                cache.remove(&new_fact);
+                if facts.is_empty() {
+                    self.map.remove(&key);
+                }
             }
         }
     }
 
-    // Replace existing fact with new one to stabilize order.
-    fn replace<T: Eq + Hash + Clone>(from: &T, to: &T, cache: &mut HashSet<T>, facts: &mut Vec<T>) {
-        let mut unique = false;
-        for i in 0..facts.len() {
-            if from == &facts[i] {
-                if unique {
-                    unique = false;
-                    break;
-                }
-                facts[i] = to.clone();
-                unique = true;
-            }
+    /// Removes all facts.
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+/// Wraps a closure that infers using an `Index` of the facts.
+///
+/// The index is updated incrementally as facts are added and removed.
+/// For multiple indices, implement `Rules` for your own type
+/// and update each index in `Rules::reset`, `Rules::added` and `Rules::removed`.
+#[derive(Clone)]
+pub struct Indexed<K, T, F> {
+    /// The index of facts.
+    pub index: Index<K, T>,
+    /// Infers new step using the index.
+    pub infer: F,
+}
+
+impl<K, T, F> Indexed<K, T, F>
+    where K: Eq + Hash, T: Clone + PartialEq, F: FnMut(&Index<K, T>, &Context<T>) -> Option<Inference<T>>
+{
+    /// Creates new rules from a key function and a closure.
+    pub fn new(key: fn(&T) -> Option<K>, infer: F) -> Indexed<K, T, F> {
+        Indexed {index: Index::new(key), infer}
+    }
+}
+
+impl<K, T, F> Rules<T> for Indexed<K, T, F>
+    where K: Eq + Hash, T: Clone + Eq + Hash, F: FnMut(&Index<K, T>, &Context<T>) -> Option<Inference<T>>
+{
+    fn infer(&mut self, cache: &HashSet<T>, facts: &[T]) -> Option<Inference<T>> {
+        self.reset(facts);
+        infer_from_facts(self, cache, facts)
+    }
+
+    fn infer_with(&mut self, ctx: &Context<T>) -> Option<Inference<T>> {
+        (self.infer)(&self.index, ctx)
+    }
+
+    fn reset(&mut self, facts: &[T]) {
+        self.index.clear();
+        for fact in facts {
+            self.index.insert(fact);
         }
-        if unique {
-            cache.remove(&from);
+    }
+
+    fn added(&mut self, fact: &T) {self.index.insert(fact)}
+
+    fn removed(&mut self, fact: &T) {self.index.remove(fact)}
+}
+
+/// The state of cycle detection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Detection<T> {
+    /// Infer new facts.
+    Solving,
+    /// A set of facts was repeated, so the solver is in a cycle.
+    ///
+    /// Stores the least amount of facts found in the cycle so far.
+    /// When the cycle is completed, the solver goes to this set of facts.
+    SearchMinimum(Vec<T>),
+}
+
+/// Tells the solver how to detect whether a set of facts has been seen before.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CycleDetection {
+    /// Uses a cuckoo filter of previous sets of facts.
+    ///
+    /// This uses constant memory, but might report a false positive.
+    /// The filter is large enough such that a false positive likely does not happen in practice.
+    #[default]
+    Probabilistic,
+    /// Stores every previous set of facts.
+    ///
+    /// A reported cycle is guaranteed to be real,
+    /// at the cost of memory proportional to the number of steps.
+    Exact,
+}
+
+/// Tells the solver when two sets of facts are the same state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StateIdentity {
+    /// Facts are compared as lists, such that order matters.
+    #[default]
+    Ordered,
+    /// Facts are compared as multisets, such that order does not matter.
+    ///
+    /// The solver might reorder facts when removing them,
+    /// so this finds cycles that are missed when comparing lists.
+    Multiset,
+}
+
+/// Tells in which order to explore multiple histories.
+///
+/// See `solve_all`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Search {
+    /// Explores the first alternative to the end before the next one.
+    #[default]
+    DepthFirst,
+    /// Explores all histories one step at a time.
+    BreadthFirst,
+    /// Explores the simplest set of facts first.
+    ///
+    /// By default, this is the set of facts with the least amount of facts,
+    /// see `SolveOptions::measure`.
+    BestFirst,
+}
+
+/// Measures how simple a set of facts is, using a cost function.
+///
+/// Used to choose the minimum set of facts in a cycle, see `SolveOptions::measure`.
+/// Sets of facts with the same cost are ordered by a hash of the state,
+/// such that the choice does not depend on where the solver entered the cycle.
+pub struct Measure<T>(Arc<Compare<T>>);
+
+type Compare<T> = dyn Fn(&[T], &[T]) -> Ordering + Send + Sync;
+
+impl<T> Measure<T> {
+    /// Creates a new measure, where a lower cost is simpler.
+    ///
+    /// E.g. `Measure::new(|facts: &[Expr]| facts.iter().map(|n| n.size()).sum::<usize>())`
+    /// measures the total size of terms.
+    pub fn new<C, F>(cost: F) -> Measure<T>
+        where C: Ord, F: Fn(&[T]) -> C + Send + Sync + 'static
+    {
+        Measure(Arc::new(move |a, b| cost(a).cmp(&cost(b))))
+    }
+}
+
+impl<T> Clone for Measure<T> {
+    fn clone(&self) -> Measure<T> {Measure(self.0.clone())}
+}
+
+impl<T> fmt::Debug for Measure<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Measure(..)")
+    }
+}
+
+/// Limits how much work the solver does before giving up,
+/// and controls how cycles are detected.
+///
+/// By default there are no limits,
+/// which assumes that the rules always lead to a cycle.
+#[derive(Clone, Debug)]
+pub struct SolveOptions<T> {
+    /// The maximum number of inference steps.
+    pub max_steps: Option<usize>,
+    /// The maximum number of facts.
+    pub max_facts: Option<usize>,
+    /// Wall-clock time after which the solver gives up.
+    pub deadline: Option<Instant>,
+    /// How to detect whether a set of facts has been seen before.
+    pub cycle_detection: CycleDetection,
+    /// When two sets of facts are the same state.
+    pub state_identity: StateIdentity,
+    /// Returns `true` for facts that represent `false`.
+    ///
+    /// When such a fact is produced, the solver stops with a contradiction.
+    /// This means that rules do not need to check for `false` themselves.
+    pub contradiction: Option<fn(&T) -> bool>,
+    /// Whether to check that consumed and kept facts exist before applying inference.
+    ///
+    /// When an inference refers to facts that do not exist,
+    /// the solver stops with `SolveOutcome::Error` instead of ignoring them.
+    pub checked: bool,
+    /// The order in which to explore multiple histories.
+    pub search: Search,
+    /// Chooses the minimum set of facts in a cycle.
+    ///
+    /// By default, the first set of facts with the least amount of facts is chosen.
+    /// This also orders `Search::BestFirst` and `Cycle::minimum`.
+    pub measure: Option<Measure<T>>,
+}
+
+impl<T> Default for SolveOptions<T> {
+    fn default() -> SolveOptions<T> {
+        SolveOptions {
+            max_steps: None,
+            max_facts: None,
+            deadline: None,
+            cycle_detection: CycleDetection::default(),
+            state_identity: StateIdentity::default(),
+            contradiction: None,
+            checked: false,
+            search: Search::DepthFirst,
+            measure: None,
         }
     }
+}
+
+impl<T: Hash> SolveOptions<T> {
+    // Returns `true` if `a` is simpler than `b`.
+    fn simpler(&self, a: &[T], b: &[T]) -> bool {simpler(self.measure.as_ref(), a, b)}
+}
 
-    let mut cache = HashSet::new();
-    for s in &facts {
-        cache.insert(s.clone());
+// Returns `true` if `a` is simpler than `b`.
+// Ties of a measure are ordered by a hash that does not depend on the state identity,
+// such that `Cycle::minimum` chooses the same set of facts as the solver.
+fn simpler<T: Hash>(measure: Option<&Measure<T>>, a: &[T], b: &[T]) -> bool {
+    match measure {
+        None => a.len() < b.len(),
+        Some(measure) => {
+            (measure.0)(a, b)
+                .then_with(|| {
+                    let identity = StateIdentity::Multiset;
+                    state_hash(a, identity).cmp(&state_hash(b, identity))
+                })
+                .then_with(|| {
+                    let identity = StateIdentity::Ordered;
+                    state_hash(a, identity).cmp(&state_hash(b, identity))
+                })
+                == Ordering::Less
+        }
     }
+}
 
-    // Cuckoo filter of previous sets of facts.
-    // Used to detect whether a given set of facts has already been inferred.
-    // Set to a value such that a false positive likely does not happen in practice.
-    let filter_capacity = 1 << 22;
-    let mut filter: CuckooFilter<DefaultHasher> = CuckooFilter::with_capacity(filter_capacity);
+impl<T> SolveOptions<T> {
+    fn is_contradiction(&self, fact: &T) -> bool {
+        self.contradiction.map(|f| f(fact)).unwrap_or(false)
+    }
 
-    let mut state = State::Solving;
+    fn exhausted(&self, steps: usize, facts: usize) -> bool {
+        if let Some(max_steps) = self.max_steps {
+            if steps >= max_steps {return true};
+        }
+        if let Some(max_facts) = self.max_facts {
+            if facts > max_facts {return true};
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {return true};
+        }
+        false
+    }
+}
 
-    loop {
-        match state {
-            State::Solving => {
-                if filter.contains(&facts) {
-                    state = State::SearchMinimum(facts.clone());
-                    filter = CuckooFilter::with_capacity(filter_capacity);
-                }
-            }
-            State::SearchMinimum(ref fa) if filter.contains(&facts) => {
-                // Completed cycle, minimum set of facts is found.
-                if fa.len() < facts.len() {
-                    facts = fa.clone();
-                }
-                break;
-            }
-            State::SearchMinimum(ref fa) if facts.len() < fa.len() => {
-                // Found less amounts of facts in cycle.
-                state = State::SearchMinimum(facts.clone());
-            }
-            _ => {}
+/// Tells how the solver terminated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SolveOutcome<T> {
+    /// A cycle was detected.
+    ///
+    /// Contains the minimum set of facts in the cycle.
+    Cycle(Vec<T>),
+    /// No rule applies to the facts.
+    Fixpoint(Vec<T>),
+    /// The limits in `SolveOptions` were reached before a cycle or fixpoint.
+    BudgetExhausted {
+        /// The facts when the solver gave up.
+        facts: Vec<T>,
+        /// The number of inference steps.
+        steps: usize,
+    },
+    /// A fact representing `false` was produced.
+    ///
+    /// See `SolveOptions::contradiction`.
+    Contradiction {
+        /// The facts when the contradiction was produced.
+        facts: Vec<T>,
+        /// The number of inference steps.
+        steps: usize,
+    },
+    /// The rules returned an inference that refers to facts that do not exist.
+    ///
+    /// See `SolveOptions::checked`.
+    Error(Box<RuleError<T>>),
+}
+
+impl<T> SolveOutcome<T> {
+    /// Returns the resulting facts.
+    pub fn facts(&self) -> &[T] {
+        match *self {
+            SolveOutcome::Cycle(ref facts) |
+            SolveOutcome::Fixpoint(ref facts) |
+            SolveOutcome::BudgetExhausted {ref facts, ..} |
+            SolveOutcome::Contradiction {ref facts, ..} => facts,
+            SolveOutcome::Error(ref err) => &err.facts,
+        }
+    }
+
+    /// Converts into the resulting facts.
+    pub fn into_facts(self) -> Vec<T> {
+        match self {
+            SolveOutcome::Cycle(facts) |
+            SolveOutcome::Fixpoint(facts) |
+            SolveOutcome::BudgetExhausted {facts, ..} |
+            SolveOutcome::Contradiction {facts, ..} => facts,
+            SolveOutcome::Error(err) => err.facts,
         }
-        filter.add(&facts);
-        if let Some(x) = infer(&cache, &facts) {
-            match x {
-                Inference::ManyTrue {from} => {
-                    remove_from(&from, &mut cache, &mut facts);
-                }
-                Inference::OneTrue {from} => {
-                    remove_from(&[from], &mut cache, &mut facts);
-                }
-                Inference::Simplify {from, to} => {
-                    remove_from(&from, &mut cache, &mut facts);
-                    facts.push(to.clone());
-                    cache.insert(to);
-                }
-                Inference::SimplifyOne {from, to} => {
-                    replace(&from, &to, &mut cache, &mut facts);
-                    cache.insert(to);
-                }
-                Inference::SimplifyMany {from, to} => {
-                    remove_from(&from, &mut cache, &mut facts);
-                    for fact in &to {
-                        cache.insert(fact.clone());
-                    }
-                    facts.extend(to.into_iter());
-                }
-                Inference::Propagate(x) => {
-                    facts.push(x.clone());
-                    cache.insert(x);
-                }
-            }
-        } else {break}
     }
-    facts
 }
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
+/// Tells which inference failed validation in checked mode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleError<T> {
+    /// The inference returned by the rules.
+    pub inference: Inference<T>,
+    /// Facts that should be consumed or kept, but do not exist.
+    ///
+    /// This is empty when the inference refers to heads by index out of range.
+    pub missing: Vec<T>,
+    /// The facts when the inference was returned.
+    pub facts: Vec<T>,
+    /// The number of inference steps before the inference.
+    pub steps: usize,
+}
+
+impl<T: fmt::Debug> fmt::Display for RuleError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Inference {:?} at step {} refers to facts that do not exist",
+               self.inference, self.steps)?;
+        if !self.missing.is_empty() {
+            write!(f, ": {:?}", self.missing)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: fmt::Debug> Error for RuleError<T> {}
+
+/// Records an inference applied by the solver.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStep<T> {
+    /// The number of inference steps before this one.
+    pub step: usize,
+    /// The inference returned by the rules.
+    pub inference: Inference<T>,
+    /// The name of the rule that fired, see `Rules::fired_rule`.
+    pub rule: Option<String>,
+    /// Facts that were removed.
+    pub consumed: Vec<T>,
+    /// Facts that were added.
+    pub produced: Vec<T>,
+}
+
+/// Records how the solver reached its result.
+///
+/// A step index refers to the set of facts after that number of inference steps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trace<T> {
+    /// Every applied inference in order.
+    pub steps: Vec<TraceStep<T>>,
+    /// The step where the solver started searching the cycle for the minimum set of facts.
+    pub cycle_start: Option<usize>,
+    /// The step where the minimum set of facts in the cycle was found.
+    pub minimum: Option<usize>,
+}
+
+/// Stores the sets of facts in a cycle.
+///
+/// A goal is proved when it belongs to some set of facts in the cycle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cycle<T> {
+    /// Every distinct set of facts in the cycle, in the order they were inferred.
+    pub states: Vec<Vec<T>>,
+    /// The number of inference steps before entering the cycle.
+    pub prefix: usize,
+}
+
+impl<T: PartialEq> Cycle<T> {
+    /// Returns `true` if some set of facts in the cycle contains `fact`.
+    pub fn contains(&self, fact: &T) -> bool {
+        self.states.iter().any(|state| state.contains(fact))
+    }
+}
+
+impl<T: Hash> Cycle<T> {
+    /// Returns the minimum set of facts, chosen like the solver does with `measure`.
+    ///
+    /// See `SolveOptions::measure`.
+    pub fn minimum(&self, measure: Option<&Measure<T>>) -> &[T] {
+        let mut min = &self.states[0];
+        for state in &self.states[1..] {
+            if simpler(measure, state, min) {min = state}
+        }
+        min
+    }
+}
+
+/// Tells how solving terminated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Termination {
+    /// No rule applied to the facts.
+    Fixpoint,
+    /// A cycle was detected and the minimum set of facts in the cycle was chosen.
+    Cycle {
+        /// The number of inference steps in the cycle.
+        length: usize,
+    },
+    /// A contradiction was reached, see `SolveOptions::contradiction` and `Inference::Split`.
+    Contradiction,
+}
+
+/// The minimum set of facts, with information about how it was reached.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Minimum<T> {
+    /// The resulting facts.
+    pub facts: Vec<T>,
+    /// Tells whether the solver reached a fixpoint, a cycle or a contradiction.
+    pub termination: Termination,
+    /// The number of inference steps.
+    pub steps: usize,
+}
+
+/// Tells where a goal was observed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proof<T> {
+    /// The set of facts containing the goal.
+    pub state: Vec<T>,
+    /// The number of inference steps before the goal was observed.
+    pub step: usize,
+}
+
+/// Solves the starting condition using the `infer` function for inference.
+///
+/// Assumes that `infer` is deterministic and leading to a cycle for every input.
+/// Finds the minimum set of facts in the cycle.
+/// Use `solve_minimum_report` to tell a fixpoint from a cycle.
+pub fn solve_minimum<T: Clone + PartialEq + Eq + Hash>(
+    facts: Vec<T>,
+    mut infer: fn(cache: &HashSet<T>, &[T]) -> Option<Inference<T>>
+) -> Vec<T> {
+    solve_minimum_with(facts, &mut infer)
+}
+
+/// Solves the starting condition using `rules` for inference.
+///
+/// Works like `solve_minimum`, but accepts closures and other types implementing `Rules`.
+/// The rules are borrowed mutably, such that any state they carry
+/// can be inspected after solving.
+pub fn solve_minimum_with<T, R>(facts: Vec<T>, rules: &mut R) -> Vec<T>
+    where T: Clone + PartialEq + Eq + Hash, R: Rules<T> + ?Sized
+{
+    solve(facts, rules, &SolveOptions::default()).into_facts()
+}
+
+/// Solves like `solve_minimum_with`, but also tells how the result was reached.
+///
+/// The result tells whether the rules reached a fixpoint, a cycle or a contradiction,
+/// the number of inference steps and the length of the cycle.
+/// The facts of a contradiction are the facts when it was reached.
+pub fn solve_minimum_report<T, R>(facts: Vec<T>, rules: &mut R) -> Minimum<T>
+    where T: Clone + PartialEq + Eq + Hash, R: Rules<T> + ?Sized
+{
+    let mut solver = Solver::new(facts, SolveOptions::default());
+    let outcome = solver.run(rules);
+    let termination = solver.termination().expect("default options have no limits and are not checked");
+    Minimum {facts: outcome.into_facts(), termination, steps: solver.steps}
+}
+
+/// Solves the starting condition using a list of `infer` functions as phases.
+///
+/// Works like `solve_minimum`, but each phase runs until no rule applies
+/// or a cycle is reached before the next phase starts.
+/// This replaces facts that only tell the rules to wait for some phase to finish.
+pub fn solve_minimum_phases<T: Clone + PartialEq + Eq + Hash>(facts: Vec<T>, phases: &[Phase<T>]) -> Vec<T> {
+    let mut phases = phases.to_vec();
+    let mut phases: Vec<&mut dyn Rules<T>> = phases.iter_mut().map(|n| n as &mut dyn Rules<T>).collect();
+    solve_phases(facts, &mut phases, &SolveOptions::default()).into_facts()
+}
+
+type Phase<T> = fn(cache: &HashSet<T>, &[T]) -> Option<Inference<T>>;
+
+/// Solves the starting condition using `rules` for inference, within the limits of `options`.
+///
+/// Unlike `solve_minimum`, this does not loop forever
+/// when the rules keep producing new facts, provided some limit is set.
+pub fn solve<T, R>(facts: Vec<T>, rules: &mut R, options: &SolveOptions<T>) -> SolveOutcome<T>
+    where T: Clone + PartialEq + Eq + Hash, R: Rules<T> + ?Sized
+{
+    Solver::new(facts, options.clone()).run(rules)
+}
+
+/// Solves the starting condition using an ordered list of rule phases.
+///
+/// See `Solver::run_phases`.
+pub fn solve_phases<T>(facts: Vec<T>, phases: &mut [&mut dyn Rules<T>], options: &SolveOptions<T>) -> SolveOutcome<T>
+    where T: Clone + PartialEq + Eq + Hash
+{
+    Solver::new(facts, options.clone()).run_phases(phases)
+}
+
+/// Solves like `solve`, but returns every set of facts in the cycle.
+///
+/// A fixpoint is treated as a cycle containing a single set of facts.
+/// Returns `Err` with the outcome when neither a cycle nor a fixpoint was reached.
+/// This includes a cycle that was detected by a false positive of `CycleDetection::Probabilistic`.
+pub fn solve_cycle<T, R>(
+    facts: Vec<T>,
+    rules: &mut R,
+    options: &SolveOptions<T>
+) -> Result<Cycle<T>, SolveOutcome<T>>
+    where T: Clone + PartialEq + Eq + Hash, R: Rules<T> + ?Sized
+{
+    let mut solver = Solver::new(facts, options.clone());
+    solver.cycle = Some(Recording {hashes: vec![], states: vec![]});
+    match solver.run(rules) {
+        x @ SolveOutcome::Cycle(_) => {
+            let Recording {hashes, states} = solver.cycle.unwrap();
+            let identity = options.state_identity;
+            // The sets of facts since cycle detection started, where the last one completed the cycle.
+            let (last, states) = states.split_last().unwrap();
+            let start = match states.iter().position(|state| same_state(state, last, identity)) {
+                Some(start) => start,
+                None => return Err(x),
+            };
+            let len = states.len() - start;
+            // Go back to the first step where the cycle repeats,
+            // since sets of facts before cycle detection started might belong to the cycle.
+            // The cycle would have been detected earlier if it repeated more than `len` steps back,
+            // so the earlier sets of facts are compared with the cycle by hash.
+            let cycle_start = hashes.len();
+            let mut prefix = cycle_start + start;
+            let stop = prefix.saturating_sub(len);
+            while prefix > stop {
+                let i = prefix - 1;
+                let same = if i >= cycle_start {
+                    same_state(&states[i - cycle_start], &states[i - cycle_start + len], identity)
+                } else {
+                    hashes[i] == state_hash(&states[i + len - cycle_start], identity)
+                };
+                if !same {break};
+                prefix -= 1;
+            }
+            let states = (prefix..prefix + len).map(|i| {
+                if i >= cycle_start {states[i - cycle_start].clone()}
+                else {states[i + len - cycle_start].clone()}
+            }).collect();
+            Ok(Cycle {states, prefix})
+        }
+        SolveOutcome::Fixpoint(facts) => Ok(Cycle {states: vec![facts], prefix: solver.steps}),
+        x => Err(x),
+    }
+}
+
+/// Proves `goal` from the starting condition using `rules` for inference.
+///
+/// The goal is a set of facts, use a slice of one element to prove a single fact.
+/// Since facts can be removed, the goal is proved when it is contained
+/// in some set of facts of the cycle that the solver ends up in.
+///
+/// Returns the first set of facts in the cycle containing the goal,
+/// or `None` if the goal is not proved.
+pub fn prove<T, R>(facts: Vec<T>, goal: &[T], rules: &mut R) -> Option<Proof<T>>
+    where T: Clone + PartialEq + Eq + Hash, R: Rules<T> + ?Sized
+{
+    let cycle = solve_cycle(facts, rules, &SolveOptions::default()).ok()?;
+    let prefix = cycle.prefix;
+    cycle.states.into_iter().enumerate()
+        .find(|(_, state)| contains_goal(state, goal))
+        .map(|(i, state)| Proof {state, step: prefix + i})
+}
+
+/// Solves like `solve`, while recording a trace of every applied inference.
+///
+/// This is useful for debugging rules.
+pub fn solve_traced<T, R>(
+    facts: Vec<T>,
+    rules: &mut R,
+    options: &SolveOptions<T>
+) -> (SolveOutcome<T>, Trace<T>)
+    where T: Clone + PartialEq + Eq + Hash, R: Rules<T> + ?Sized
+{
+    let mut solver = Solver::new(facts, options.clone());
+    solver.trace = Some(Trace {steps: vec![], cycle_start: None, minimum: None});
+    let outcome = solver.run(rules);
+    (outcome, solver.trace.unwrap())
+}
+
+// Cuckoo filter capacity.
+// Set to a value such that a false positive likely does not happen in practice.
+const FILTER_CAPACITY: u64 = 1 << 22;
+
+// Returns a hash of the facts which respects the state identity.
+fn state_hash<T: Hash>(facts: &[T], identity: StateIdentity) -> u64 {
+    fn hash<U: Hash + ?Sized>(x: &U) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        x.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    match identity {
+        StateIdentity::Ordered => hash(facts),
+        StateIdentity::Multiset => {
+            // Adding hashes of facts is independent of order.
+            let sum = facts.iter().fold(0u64, |sum, fact| sum.wrapping_add(hash(fact)));
+            hash(&(facts.len(), sum))
+        }
+    }
+}
+
+// Returns `true` if two sets of facts are the same state.
+fn same_state<T: Eq + Hash>(a: &[T], b: &[T], identity: StateIdentity) -> bool {
+    match identity {
+        StateIdentity::Ordered => a == b,
+        StateIdentity::Multiset => {
+            if a.len() != b.len() {return false};
+            let mut counts: HashMap<&T, isize> = HashMap::new();
+            for fact in a {*counts.entry(fact).or_insert(0) += 1}
+            for fact in b {*counts.entry(fact).or_insert(0) -= 1}
+            counts.values().all(|&n| n == 0)
+        }
+    }
+}
+
+// Returns `true` if the facts contain every copy of facts in the goal.
+fn contains_goal<T: PartialEq>(facts: &[T], goal: &[T]) -> bool {
+    goal.iter().all(|g| {
+        goal.iter().filter(|n| *n == g).count() <= facts.iter().filter(|n| *n == g).count()
+    })
+}
+
+// Returns copies of facts that do not exist.
+fn missing<'a, T, I>(counts: &HashMap<T, usize>, facts: I) -> Vec<T>
+    where T: 'a + Clone + Eq + Hash, I: IntoIterator<Item = (&'a T, usize)>
+{
+    let mut required: HashMap<&T, usize> = HashMap::new();
+    let mut missing = vec![];
+    for (fact, n) in facts {
+        let count = counts.get(fact).cloned().unwrap_or(0);
+        let r = required.entry(fact).or_insert(0);
+        for _ in (*r).max(count)..*r + n {
+            missing.push(fact.clone());
+        }
+        *r += n;
+    }
+    missing
+}
+
+// Stores previous sets of facts.
+// Used to detect whether a given set of facts has already been inferred.
+struct Seen<T> {
+    identity: StateIdentity,
+    store: Store<T>,
+}
+
+enum Store<T> {
+    Filter(CuckooFilter<DefaultHasher>),
+    // Stores full sets of facts with their copies by hash.
+    Exact(HashMap<u64, Vec<State<T>>>),
+}
+
+// A set of facts with the copies of every fact, which are empty when not counted.
+type State<T> = (Vec<T>, Vec<usize>);
+
+impl<T: Clone + Eq + Hash> Seen<T> {
+    fn new(cycle_detection: CycleDetection, identity: StateIdentity) -> Seen<T> {
+        let store = match cycle_detection {
+            CycleDetection::Probabilistic =>
+                Store::Filter(CuckooFilter::with_capacity(FILTER_CAPACITY)),
+            CycleDetection::Exact => Store::Exact(HashMap::new()),
+        };
+        Seen {identity, store}
+    }
+
+    fn contains(&self, db: &Database<T>) -> bool {
+        let hash = db.state_hash(self.identity);
+        match self.store {
+            Store::Filter(ref filter) => filter.contains(&hash),
+            Store::Exact(ref map) => map.get(&hash).map(|states| {
+                states.iter().any(|(facts, copies)| db.same_state(facts, copies, self.identity))
+            }).unwrap_or(false),
+        }
+    }
+
+    fn add(&mut self, db: &Database<T>) {
+        let hash = db.state_hash(self.identity);
+        match self.store {
+            Store::Filter(ref mut filter) => {filter.add(&hash);}
+            Store::Exact(ref mut map) => {
+                map.entry(hash).or_default().push((db.facts.clone(), db.copies().to_vec()))
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        match self.store {
+            Store::Filter(ref mut filter) => *filter = CuckooFilter::with_capacity(FILTER_CAPACITY),
+            Store::Exact(ref mut map) => map.clear(),
+        }
+    }
+}
+
+// Stores facts with the information rules can query.
+//
+// In counted mode, every distinct fact is stored once with its number of copies.
+#[derive(Clone)]
+struct Database<T> {
+    facts: Vec<T>,
+    cache: HashSet<T>,
+    // The number of copies of each fact.
+    counts: HashMap<T, usize>,
+    // Identifies each fact instance.
+    ids: Vec<usize>,
+    next_id: usize,
+    // Rules and fact instances of propagations that fired.
+    history: HashSet<(usize, Vec<usize>)>,
+    // The copies of every distinct fact, when used by `solve_counted`.
+    counted: Option<Counted<T>>,
+}
+
+impl<T: Clone + PartialEq + Eq + Hash> Database<T> {
+    fn new(facts: Vec<T>) -> Database<T> {
+        let mut db = Database {
+            facts: Vec::with_capacity(facts.len()),
+            cache: HashSet::new(),
+            counts: HashMap::new(),
+            ids: Vec::with_capacity(facts.len()),
+            next_id: 0,
+            history: HashSet::new(),
+            counted: None,
+        };
+        for fact in facts {
+            db.insert(fact);
+        }
+        db
+    }
+
+    // Creates a database in counted mode.
+    fn counted(facts: Vec<T>) -> Database<T> {
+        let mut db = Database::new(vec![]);
+        db.counted = Some(Counted::new(&[], vec![]));
+        for fact in facts {
+            db.insert(fact);
+        }
+        db
+    }
+
+    // Sets the facts together with the identity of each fact instance,
+    // such that the propagation history still refers to the same instances.
+    // In counted mode, `copies` are the numbers of copies of the facts.
+    fn set_facts(&mut self, facts: Vec<T>, ids: Vec<usize>, copies: Vec<usize>) {
+        self.cache.clear();
+        self.counts.clear();
+        if self.counted.is_some() {
+            for (fact, &n) in facts.iter().zip(&copies) {
+                self.count_up(fact, n);
+            }
+            self.counted = Some(Counted::new(&facts, copies));
+        } else {
+            for fact in &facts {
+                self.count_up(fact, 1);
+            }
+        }
+        self.facts = facts;
+        self.ids = ids;
+    }
+
+    // Returns the numbers of copies of the facts in counted mode, otherwise nothing.
+    fn copies(&self) -> &[usize] {
+        match self.counted {
+            Some(ref counted) => &counted.copies,
+            None => &[],
+        }
+    }
+
+    // Returns the number of facts, counting every copy.
+    fn len(&self) -> usize {
+        match self.counted {
+            Some(ref counted) => counted.len,
+            None => self.facts.len(),
+        }
+    }
+
+    // Returns every copy of the facts.
+    fn to_facts(&self) -> Vec<T> {expand(&self.facts, self.copies()).into_owned()}
+
+    // Returns a hash of the facts which respects the state identity.
+    fn state_hash(&self, identity: StateIdentity) -> u64 {
+        match self.counted {
+            Some(ref counted) => counted.state_hash(&self.facts, identity),
+            None => state_hash(&self.facts, identity),
+        }
+    }
+
+    // Returns `true` if the facts with their copies are the same state as this one.
+    fn same_state(&self, facts: &[T], copies: &[usize], identity: StateIdentity) -> bool {
+        match (identity, &self.counted) {
+            (StateIdentity::Ordered, _) => self.facts[..] == *facts && self.copies() == copies,
+            (StateIdentity::Multiset, Some(_)) => {
+                self.facts.len() == facts.len() &&
+                facts.iter().zip(copies).all(|(fact, n)| self.counts.get(fact) == Some(n))
+            }
+            (StateIdentity::Multiset, None) => same_state(&self.facts, facts, identity),
+        }
+    }
+
+    fn new_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    fn context(&self) -> Context<'_, T> {
+        Context {
+            cache: &self.cache,
+            counts: &self.counts,
+            facts: &self.facts,
+            ids: &self.ids,
+            history: &self.history,
+        }
+    }
+
+    fn count_up(&mut self, fact: &T, n: usize) {
+        if let Some(count) = self.counts.get_mut(fact) {
+            *count += n;
+            return;
+        }
+        self.counts.insert(fact.clone(), n);
+        self.cache.insert(fact.clone());
+    }
+
+    fn count_down(&mut self, fact: &T, n: usize) {
+        let count = self.counts.get_mut(fact).unwrap();
+        *count -= n;
+        if *count == 0 {
+            self.counts.remove(fact);
+            self.cache.remove(fact);
+        }
+    }
+
+    fn insert(&mut self, fact: T) {self.insert_copies(fact, 1)}
+
+    // Inserts `n` copies of a fact.
+    fn insert_copies(&mut self, fact: T, n: usize) {
+        if n == 0 {return};
+        let counted = match self.counted {
+            Some(ref mut counted) => counted,
+            None => {
+                for _ in 1..n {
+                    self.count_up(&fact, 1);
+                    self.push(fact.clone());
+                }
+                self.count_up(&fact, 1);
+                self.push(fact);
+                return;
+            }
+        };
+        counted.len += n;
+        match counted.positions.get(&fact) {
+            Some(&i) => counted.copies[i] += n,
+            None => {
+                counted.positions.insert(fact.clone(), self.facts.len());
+                counted.copies.push(n);
+                self.count_up(&fact, n);
+                self.push(fact);
+                return;
+            }
+        }
+        self.count_up(&fact, n);
+    }
+
+    // Appends a fact instance.
+    fn push(&mut self, fact: T) {
+        self.facts.push(fact);
+        let id = self.new_id();
+        self.ids.push(id);
+    }
+
+    // Checks that consumed and kept facts exist.
+    fn validate(&self, inference: &Inference<T>, steps: usize) -> Result<(), Box<RuleError<T>>> {
+        let heads = match *inference {
+            Inference::PropagateOnce {ref heads, ..} => heads.iter().all(|&i| i < self.facts.len()),
+            _ => true,
+        };
+        let missing = missing(&self.counts, inference.required());
+        if heads && missing.is_empty() {Ok(())} else {
+            Err(Box::new(RuleError {
+                inference: inference.clone(),
+                missing,
+                facts: self.to_facts(),
+                steps,
+            }))
+        }
+    }
+
+    // Returns the position of the first copy of a fact.
+    fn position(&self, fact: &T) -> Option<usize> {
+        match self.counted {
+            Some(ref counted) => counted.positions.get(fact).cloned(),
+            None => self.facts.iter().position(|n| n == fact),
+        }
+    }
+
+    // Removes the first copy of a fact.
+    // Returns `None` if the fact is not present.
+    fn remove(&mut self, fact: &T) -> Option<T> {
+        let i = self.position(fact)?;
+        if let Some(ref mut counted) = self.counted {
+            if counted.copies[i] > 1 {
+                counted.copies[i] -= 1;
+                counted.len -= 1;
+                self.count_down(fact, 1);
+                return Some(fact.clone());
+            }
+        }
+        Some(self.remove_at(i))
+    }
+
+    // Removes up to `n` copies of a fact.
+    // Returns the number of removed copies.
+    fn remove_copies(&mut self, fact: &T, n: usize) -> usize {
+        let counted = match self.counted {
+            Some(ref mut counted) => counted,
+            None => return (0..n).take_while(|_| self.remove(fact).is_some()).count(),
+        };
+        let i = match counted.positions.get(fact) {
+            Some(&i) => i,
+            None => return 0,
+        };
+        if n < counted.copies[i] {
+            counted.copies[i] -= n;
+            counted.len -= n;
+            self.count_down(fact, n);
+            return n;
+        }
+        let n = counted.copies[i];
+        self.remove_at(i);
+        n
+    }
+
+    // Removes the fact at a position with all its copies.
+    fn remove_at(&mut self, i: usize) -> T {
+        let last = self.facts.len() - 1;
+        let removed = self.facts.swap_remove(i);
+        self.ids.swap_remove(i);
+        let n = match self.counted {
+            Some(ref mut counted) => {
+                let n = counted.copies.swap_remove(i);
+                counted.len -= n;
+                counted.positions.remove(&removed);
+                if i != last {
+                    // The last fact was moved into the removed position.
+                    counted.positions.insert(self.facts[i].clone(), i);
+                }
+                n
+            }
+            None => 1,
+        };
+        self.count_down(&removed, n);
+        removed
+    }
+
+    // Replace existing fact with new one to stabilize order.
+    // Returns `None` if the fact is not present.
+    fn replace(&mut self, from: &T, to: &T) -> Option<T> {
+        let i = self.position(from)?;
+        if let Some(ref mut counted) = self.counted {
+            if counted.copies[i] > 1 || counted.positions.contains_key(to) {
+                // Other copies stay at their position.
+                self.remove(from);
+                self.insert(to.clone());
+                return Some(from.clone());
+            }
+            counted.positions.remove(from);
+            counted.positions.insert(to.clone(), i);
+        }
+        let removed = std::mem::replace(&mut self.facts[i], to.clone());
+        self.ids[i] = self.new_id();
+        self.count_down(from, 1);
+        self.count_up(to, 1);
+        Some(removed)
+    }
+
+    // Applies inference and returns the facts consumed and produced.
+    fn apply(&mut self, inference: &Inference<T>) -> (Vec<T>, Vec<T>) {
+        match *inference {
+            Inference::ManyTrue {ref from} => {
+                (from.iter().filter_map(|n| self.remove(n)).collect(), vec![])
+            }
+            Inference::OneTrue {ref from} => {
+                (self.remove(from).into_iter().collect(), vec![])
+            }
+            Inference::Simplify {ref from, ref to} => {
+                let consumed = from.iter().filter_map(|n| self.remove(n)).collect();
+                self.insert(to.clone());
+                (consumed, vec![to.clone()])
+            }
+            Inference::SimplifyOne {ref from, ref to} => {
+                match self.replace(from, to) {
+                    Some(consumed) => (vec![consumed], vec![to.clone()]),
+                    None => (vec![], vec![]),
+                }
+            }
+            Inference::SimplifyMany {ref from, ref to} => {
+                let consumed = from.iter().filter_map(|n| self.remove(n)).collect();
+                for fact in to {
+                    self.insert(fact.clone());
+                }
+                (consumed, to.clone())
+            }
+            Inference::Propagate(ref x) => {
+                self.insert(x.clone());
+                (vec![], vec![x.clone()])
+            }
+            Inference::Simpagation {ref remove, ref add, ..} => {
+                if !missing(&self.counts, inference.required()).is_empty() {return (vec![], vec![])};
+                let consumed = remove.iter().filter_map(|n| self.remove(n)).collect();
+                for fact in add {
+                    self.insert(fact.clone());
+                }
+                (consumed, add.clone())
+            }
+            Inference::PropagateOnce {rule, ref heads, ref to} => {
+                if heads.iter().any(|&i| i >= self.ids.len()) {return (vec![], vec![])};
+                let ids = heads.iter().map(|&i| self.ids[i]).collect();
+                if !self.history.insert((rule, ids)) {return (vec![], vec![])};
+                for fact in to {
+                    self.insert(fact.clone());
+                }
+                (vec![], to.clone())
+            }
+            Inference::Counted {ref from, ref to} => {
+                let mut consumed = vec![];
+                for &(ref fact, n) in from {
+                    let n = self.remove_copies(fact, n);
+                    consumed.extend(std::iter::repeat(fact).take(n).cloned());
+                }
+                let mut produced = vec![];
+                for &(ref fact, n) in to {
+                    self.insert_copies(fact.clone(), n);
+                    produced.extend(std::iter::repeat(fact).take(n).cloned());
+                }
+                (consumed, produced)
+            }
+            // Splits are resolved by the solver before applying.
+            Inference::Split {..} => (vec![], vec![]),
+        }
+    }
+}
+
+// Solves a branch for every case of a split.
+// Returns an inference replacing `from` with the facts from `Rules::split`.
+// Returns `None` when no case survived.
+fn split<T, R>(
+    facts: &[T],
+    from: &T,
+    cases: &[T],
+    rules: &mut R,
+    options: &SolveOptions<T>,
+    memo: &mut HashMap<Vec<T>, bool>
+) -> Option<Inference<T>>
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized
+{
+    split_with(facts, from, cases, rules, memo, |rules, branches, memo| {
+        survive(branches, rules, options, false, memo)
+    })
+}
+
+// Splits like `split`, using `survive` to solve branches that are not remembered.
+fn split_with<T, R, F>(
+    facts: &[T],
+    from: &T,
+    cases: &[T],
+    rules: &mut R,
+    memo: &mut HashMap<Vec<T>, bool>,
+    survive: F
+) -> Option<Inference<T>>
+    where T: Clone + Eq + Hash,
+          R: Rules<T> + ?Sized,
+          F: FnOnce(&mut R, Vec<Vec<T>>, &mut HashMap<Vec<T>, bool>) -> Vec<bool>
+{
+    let mut branch = facts.to_vec();
+    if let Some(i) = branch.iter().position(|n| n == from) {
+        branch.remove(i);
+    }
+    let branches: Vec<Vec<T>> = cases.iter().map(|case| {
+        let mut facts = branch.clone();
+        facts.push(case.clone());
+        facts
+    }).collect();
+    let mut unsolved: Vec<Vec<T>> = vec![];
+    for facts in &branches {
+        if !memo.contains_key(facts) && !unsolved.contains(facts) {
+            unsolved.push(facts.clone());
+        }
+    }
+    let survived = survive(rules, unsolved.clone(), memo);
+    memo.extend(unsolved.into_iter().zip(survived));
+    let survivors: Vec<T> = cases.iter().zip(&branches)
+        .filter(|&(_, facts)| memo[facts])
+        .map(|(case, _)| case.clone())
+        .collect();
+    if survivors.is_empty() {return None};
+    let mut to = rules.split(from, survivors);
+    Some(if to.len() == 1 {
+        Inference::SimplifyOne {from: from.clone(), to: to.pop().unwrap()}
+    } else {
+        Inference::SimplifyMany {from: vec![from.clone()], to}
+    })
+}
+
+// Solves branches one after another, storing copies of facts when `counted`.
+// Returns `true` for every branch that does not end in a contradiction.
+fn survive<T, R>(
+    branches: Vec<Vec<T>>,
+    rules: &mut R,
+    options: &SolveOptions<T>,
+    counted: bool,
+    memo: &mut HashMap<Vec<T>, bool>
+) -> Vec<bool>
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized
+{
+    branches.into_iter().map(|facts| {
+        let db = if counted {Database::counted(facts)} else {Database::new(facts)};
+        // Share results with nested splits.
+        let mut solver = Solver::with_db(db, options.clone());
+        solver.splits = std::mem::take(memo);
+        let outcome = solver.run_rules(rules);
+        *memo = solver.splits;
+        !matches!(outcome, SolveOutcome::Contradiction {..})
+    }).collect()
+}
+
+// Records the sets of facts after every step, used by `solve_cycle`.
+// Only hashes are kept until cycle detection starts, to save memory.
+struct Recording<T> {
+    // Hashes of the sets of facts before cycle detection started.
+    hashes: Vec<u64>,
+    // The sets of facts since cycle detection started.
+    states: Vec<Vec<T>>,
+}
+
+/// A solving session that keeps facts between runs.
+///
+/// Use this when facts are added over time.
+/// After adding or retracting facts, `run` continues from the last reached state
+/// instead of solving from scratch.
+/// The propagation history is kept, such that `Inference::PropagateOnce`
+/// does not fire again for the same facts.
+pub struct Solver<T> {
+    options: SolveOptions<T>,
+    db: Database<T>,
+    seen: Seen<T>,
+    state: Detection<T>,
+    // Identifies the fact instances of the minimum set of facts in the cycle.
+    minimum_ids: Vec<usize>,
+    // The copies of the minimum set of facts in the cycle, when counted.
+    minimum_copies: Vec<usize>,
+    steps: usize,
+    // The number of steps before the current run, where the budget starts.
+    run_start: usize,
+    // The step where the cycle was first detected.
+    cycle_start: Option<usize>,
+    // How the last run terminated.
+    termination: Option<Termination>,
+    trace: Option<Trace<T>>,
+    // Sets of facts after every step, when recording the cycle.
+    cycle: Option<Recording<T>>,
+    // Whether branches of splits survived.
+    splits: HashMap<Vec<T>, bool>,
+}
+
+impl<T: Clone + PartialEq + Eq + Hash> Solver<T> {
+    /// Creates a new session with starting facts.
+    pub fn new(facts: Vec<T>, options: SolveOptions<T>) -> Solver<T> {
+        Solver::with_db(Database::new(facts), options)
+    }
+
+    // Creates a new session with the facts stored in `db`.
+    fn with_db(db: Database<T>, options: SolveOptions<T>) -> Solver<T> {
+        Solver {
+            db,
+            seen: Seen::new(options.cycle_detection, options.state_identity),
+            state: Detection::Solving,
+            minimum_ids: vec![],
+            minimum_copies: vec![],
+            steps: 0,
+            run_start: 0,
+            cycle_start: None,
+            termination: None,
+            trace: None,
+            cycle: None,
+            splits: HashMap::new(),
+            options,
+        }
+    }
+
+    /// Returns the current facts.
+    pub fn facts(&self) -> &[T] {&self.db.facts}
+
+    /// Returns the number of inference steps in all runs.
+    ///
+    /// Limits in `SolveOptions` apply to the steps of each run,
+    /// such that a session can continue after the budget was exhausted.
+    pub fn steps(&self) -> usize {self.steps}
+
+    /// Returns how the last run terminated.
+    ///
+    /// Returns `None` before the first run,
+    /// or when the last run ended otherwise, e.g. when the budget was exhausted.
+    pub fn termination(&self) -> Option<Termination> {self.termination}
+
+    /// Adds new facts.
+    pub fn add_facts(&mut self, facts: Vec<T>) {
+        for fact in facts {
+            self.db.insert(fact);
+        }
+        self.restart();
+    }
+
+    /// Retracts one copy of a fact.
+    ///
+    /// Returns `false` if the fact does not exist.
+    pub fn retract(&mut self, fact: &T) -> bool {
+        if self.db.remove(fact).is_none() {return false};
+        self.restart();
+        true
+    }
+
+    /// Solves from the current facts using `rules` for inference.
+    ///
+    /// Returns when reaching a cycle, a fixpoint or some limit in `SolveOptions`.
+    /// The facts are then kept for the next run.
+    pub fn run<R>(&mut self, rules: &mut R) -> SolveOutcome<T>
+        where R: Rules<T> + ?Sized
+    {
+        self.begin_run();
+        self.run_rules(rules)
+    }
+
+    // Starts the budget over and forgets the results of splits, since the rules may have changed.
+    fn begin_run(&mut self) {
+        self.run_start = self.steps;
+        self.splits.clear();
+    }
+
+    // Runs like `run`, within the budget of the current run.
+    fn run_rules<R>(&mut self, rules: &mut R) -> SolveOutcome<T>
+        where R: Rules<T> + ?Sized
+    {
+        let options = self.options.clone();
+        let counted = self.db.counted.is_some();
+        self.run_with(rules, &mut |rules, branches, memo| {
+            survive(branches, rules, &options, counted, memo)
+        })
+    }
+
+    /// Solves from the current facts using an ordered list of rule phases.
+    ///
+    /// Each phase runs until no rule applies or a cycle is reached,
+    /// with cycle detection starting over for every phase.
+    /// The next phase continues from the facts of the previous one.
+    ///
+    /// Stops at the first phase that ends otherwise, e.g. in a contradiction.
+    /// Returns the outcome of the last phase that ran.
+    /// Limits in `SolveOptions` apply to the steps of all phases together.
+    pub fn run_phases(&mut self, phases: &mut [&mut dyn Rules<T>]) -> SolveOutcome<T> {
+        self.run_stages(phases.iter_mut(), |solver, rules| solver.run_rules(*rules))
+    }
+
+    // Runs `run` for every stage, restarting cycle detection in between.
+    // Stops at the first stage that ends otherwise than in a fixpoint or a cycle.
+    fn run_stages<I, F>(&mut self, stages: I, mut run: F) -> SolveOutcome<T>
+        where I: IntoIterator, F: FnMut(&mut Solver<T>, I::Item) -> SolveOutcome<T>
+    {
+        self.begin_run();
+        let mut outcome = SolveOutcome::Fixpoint(self.db.to_facts());
+        for stage in stages {
+            self.restart();
+            outcome = run(self, stage);
+            match outcome {
+                SolveOutcome::Fixpoint(_) | SolveOutcome::Cycle(_) => {}
+                _ => break,
+            }
+        }
+        outcome
+    }
+
+    // Starts cycle detection over, since facts or rules changed.
+    fn restart(&mut self) {
+        self.seen.clear();
+        self.state = Detection::Solving;
+        self.cycle_start = None;
+        self.splits.clear();
+        if let Some(ref mut recording) = self.cycle {
+            recording.hashes.clear();
+            recording.states.clear();
+        }
+    }
+
+    // Returns `true` when a cycle is completed.
+    // The facts are then set to the minimum set of facts in the cycle.
+    fn detect_cycle(&mut self) -> bool {
+        if let Some(ref mut recording) = self.cycle {
+            if self.cycle_start.is_some() {
+                recording.states.push(self.db.to_facts());
+            } else {
+                recording.hashes.push(self.db.state_hash(self.options.state_identity));
+            }
+        }
+        match self.state {
+            Detection::Solving if self.seen.contains(&self.db) => {
+                if let Some(ref mut recording) = self.cycle {
+                    recording.hashes.pop();
+                    recording.states.push(self.db.to_facts());
+                }
+                self.state = Detection::SearchMinimum(self.db.facts.clone());
+                self.minimum_ids = self.db.ids.clone();
+                self.minimum_copies = self.db.copies().to_vec();
+                self.seen.clear();
+                self.cycle_start = Some(self.steps);
+                if let Some(ref mut trace) = self.trace {
+                    trace.cycle_start = Some(self.steps);
+                    trace.minimum = Some(self.steps);
+                }
+            }
+            Detection::SearchMinimum(ref fa) if self.seen.contains(&self.db) => {
+                // Completed cycle, minimum set of facts is found.
+                if simpler_counted(&self.options, fa, &self.minimum_copies, &self.db.facts, self.db.copies()) {
+                    let fa = fa.clone();
+                    self.db.set_facts(fa, self.minimum_ids.clone(), self.minimum_copies.clone());
+                }
+                let length = self.steps - self.cycle_start.unwrap();
+                self.termination = Some(Termination::Cycle {length});
+                return true;
+            }
+            Detection::SearchMinimum(ref fa)
+                if simpler_counted(&self.options, &self.db.facts, self.db.copies(), fa, &self.minimum_copies) =>
+            {
+                // Found simpler set of facts in cycle.
+                self.state = Detection::SearchMinimum(self.db.facts.clone());
+                self.minimum_ids = self.db.ids.clone();
+                self.minimum_copies = self.db.copies().to_vec();
+                if let Some(ref mut trace) = self.trace {
+                    trace.minimum = Some(self.steps);
+                }
+            }
+            _ => {}
+        }
+        self.seen.add(&self.db);
+        false
+    }
+
+    /// Returns an iterator applying one inference at a time.
+    ///
+    /// The iterator ends when solving is done, see `Steps::outcome`.
+    pub fn iter_steps<'a, R>(&'a mut self, rules: &'a mut R) -> Steps<'a, T, R>
+        where R: Rules<T> + ?Sized
+    {
+        self.begin_run();
+        Steps {solver: self, rules, started: false, outcome: None}
+    }
+
+    // Runs like `run`, using `survive` to solve branches of splits.
+    fn run_with<R, F>(&mut self, rules: &mut R, survive: &mut F) -> SolveOutcome<T>
+        where R: Rules<T> + ?Sized,
+              F: FnMut(&mut R, Vec<Vec<T>>, &mut HashMap<Vec<T>, bool>) -> Vec<bool>
+    {
+        if let Some(outcome) = self.start(rules) {return outcome};
+        loop {
+            if let (_, Some(outcome)) = self.step_with(rules, survive) {return outcome};
+        }
+    }
+
+    // Checks the starting facts and prepares the rules.
+    // Returns the outcome if there is a contradiction.
+    fn start<R>(&mut self, rules: &mut R) -> Option<SolveOutcome<T>>
+        where R: Rules<T> + ?Sized
+    {
+        self.termination = None;
+        if self.db.facts.iter().any(|n| self.options.is_contradiction(n)) {
+            self.termination = Some(Termination::Contradiction);
+            return Some(SolveOutcome::Contradiction {facts: self.db.to_facts(), steps: self.steps});
+        }
+        rules.reset(&self.db.facts);
+        None
+    }
+
+    // Infers and applies one step.
+    // Returns the applied inference with the name of the rule that fired,
+    // and the outcome when solving is done.
+    fn step_with<R, F>(&mut self, rules: &mut R, survive: &mut F) -> (Option<Fired<T>>, Option<SolveOutcome<T>>)
+        where R: Rules<T> + ?Sized,
+              F: FnMut(&mut R, Vec<Vec<T>>, &mut HashMap<Vec<T>, bool>) -> Vec<bool>
+    {
+        if self.detect_cycle() {
+            return (None, Some(SolveOutcome::Cycle(self.db.to_facts())));
+        }
+        if self.options.exhausted(self.steps - self.run_start, self.db.len()) {
+            // The next run continues from these facts, which are already seen.
+            self.restart();
+            let facts = self.db.to_facts();
+            return (None, Some(SolveOutcome::BudgetExhausted {facts, steps: self.steps}));
+        }
+        let x = match rules.infer_with(&self.db.context()) {
+            Some(x) => x,
+            None => {
+                self.termination = Some(Termination::Fixpoint);
+                return (None, Some(SolveOutcome::Fixpoint(self.db.to_facts())));
+            }
+        };
+        if self.options.checked {
+            if let Err(err) = self.db.validate(&x, self.steps) {
+                return (None, Some(SolveOutcome::Error(err)));
+            }
+        }
+        // Get the name before solving branches, which might fire other rules.
+        let rule = rules.fired_rule().map(String::from);
+        // Whether no case of a split survived.
+        let mut dead = false;
+        let (consumed, produced) = match x {
+            Inference::Split {ref from, ref cases} => {
+                let y = split_with(&self.db.to_facts(), from, cases, rules, &mut self.splits, &mut *survive);
+                rules.reset(&self.db.facts);
+                match y {
+                    Some(y) => self.db.apply(&y),
+                    None => {
+                        dead = true;
+                        (vec![], vec![])
+                    }
+                }
+            }
+            _ => self.db.apply(&x),
+        };
+        for fact in &consumed {rules.removed(fact)}
+        for fact in &produced {rules.added(fact)}
+        let contradiction = dead || produced.iter().any(|n| self.options.is_contradiction(n));
+        if let Some(ref mut trace) = self.trace {
+            trace.steps.push(TraceStep {
+                step: self.steps,
+                inference: x.clone(),
+                rule: rule.clone(),
+                consumed,
+                produced,
+            });
+        }
+        self.steps += 1;
+        if contradiction {
+            self.termination = Some(Termination::Contradiction);
+            let facts = self.db.to_facts();
+            return (Some((x, rule)), Some(SolveOutcome::Contradiction {facts, steps: self.steps}));
+        }
+        (Some((x, rule)), None)
+    }
+}
+
+// An applied inference with the name of the rule that fired.
+type Fired<T> = (Inference<T>, Option<String>);
+
+/// An inference step applied by the solver.
+///
+/// See `Solver::iter_steps`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step<T> {
+    /// The index of the step.
+    pub step: usize,
+    /// The inference returned by the rules.
+    pub inference: Inference<T>,
+    /// The name of the rule that fired, see `Rules::fired_rule`.
+    pub rule: Option<String>,
+    /// The facts after applying the inference.
+    pub facts: Vec<T>,
+    /// The state of cycle detection when the inference was applied.
+    pub detection: Detection<T>,
+}
+
+/// Iterates over inference steps of a solver.
+pub struct Steps<'a, T: 'a, R: 'a + ?Sized> {
+    solver: &'a mut Solver<T>,
+    rules: &'a mut R,
+    started: bool,
+    outcome: Option<SolveOutcome<T>>,
+}
+
+impl<'a, T, R> Steps<'a, T, R>
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized
+{
+    /// Returns how solving ended, after the iterator returned `None`.
+    pub fn outcome(&self) -> Option<&SolveOutcome<T>> {self.outcome.as_ref()}
+}
+
+impl<'a, T, R> Iterator for Steps<'a, T, R>
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized
+{
+    type Item = Step<T>;
+
+    fn next(&mut self) -> Option<Step<T>> {
+        if self.outcome.is_some() {return None};
+        if !self.started {
+            self.started = true;
+            self.outcome = self.solver.start(self.rules);
+            if self.outcome.is_some() {return None};
+        }
+        let options = self.solver.options.clone();
+        let counted = self.solver.db.counted.is_some();
+        let (inference, outcome) = self.solver.step_with(self.rules, &mut |rules, branches, memo| {
+            survive(branches, rules, &options, counted, memo)
+        });
+        self.outcome = outcome;
+        inference.map(|(inference, rule)| Step {
+            step: self.solver.steps - 1,
+            inference,
+            rule,
+            facts: self.solver.db.to_facts(),
+            detection: self.solver.state.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Alternates between `1` and `2, 3`.
+    fn alternate(cache: &HashSet<u32>, _facts: &[u32]) -> Option<Inference<u32>> {
+        if cache.contains(&1) {
+            Some(Inference::SimplifyMany {from: vec![1], to: vec![2, 3]})
+        } else {
+            Some(Inference::Simplify {from: vec![2, 3], to: 1})
+        }
+    }
+
+    // Counts to `3`, replaces it with `1, 2` and counts again from `1`.
+    fn count_then_cycle(_cache: &HashSet<u32>, facts: &[u32]) -> Option<Inference<u32>> {
+        match *facts {
+            [x] if x < 3 => Some(Inference::SimplifyOne {from: x, to: x + 1}),
+            [3] => Some(Inference::SimplifyMany {from: vec![3], to: vec![1, 2]}),
+            _ => Some(Inference::ManyTrue {from: vec![2]}),
+        }
+    }
+
+    #[test]
+    fn it_works() {
+    }
+
+    #[test]
+    fn closure_rules() {
+        let mut fired = 0;
+        let res = solve_minimum_with(vec![1, 2, 3], &mut |cache: &HashSet<u32>, _facts: &[u32]| {
+            if cache.contains(&2) {
+                fired += 1;
+                Some(Inference::OneTrue {from: 2})
+            } else {None}
+        });
+        assert_eq!(res, vec![1, 3]);
+        assert_eq!(fired, 1);
+    }
+
+    #[test]
+    fn stateful_rules() {
+        // Removes at most `limit` facts.
+        struct Limit {limit: usize}
+
+        impl Rules<u32> for Limit {
+            fn infer(&mut self, _cache: &HashSet<u32>, facts: &[u32]) -> Option<Inference<u32>> {
+                if self.limit == 0 {return None};
+                self.limit -= 1;
+                facts.first().map(|&from| Inference::OneTrue {from})
+            }
+        }
+
+        let mut rules = Limit {limit: 2};
+        let res = solve_minimum_with(vec![1, 2, 3], &mut rules);
+        assert_eq!(res.len(), 1);
+        assert_eq!(rules.limit, 0);
+    }
+
+    #[test]
+    fn budget() {
+        // Counts forever.
+        let mut rules = |_cache: &HashSet<u32>, facts: &[u32]| {
+            Some(Inference::SimplifyOne {from: facts[0], to: facts[0] + 1})
+        };
+        let options = SolveOptions {max_steps: Some(10), ..Default::default()};
+        assert_eq!(solve(vec![0], &mut rules, &options),
+                   SolveOutcome::BudgetExhausted {facts: vec![10], steps: 10});
+
+        // The budget applies to every run of a session.
+        let mut solver = Solver::new(vec![0], options.clone());
+        assert_eq!(solver.run(&mut rules), SolveOutcome::BudgetExhausted {facts: vec![10], steps: 10});
+        assert_eq!(solver.run(&mut rules), SolveOutcome::BudgetExhausted {facts: vec![20], steps: 20});
+        solver.add_facts(vec![0]);
+        assert_eq!(solver.run(&mut rules), SolveOutcome::BudgetExhausted {facts: vec![30, 0], steps: 30});
+
+        let options = SolveOptions {
+            deadline: Some(Instant::now() + std::time::Duration::from_millis(10)),
+            ..Default::default()
+        };
+        match solve(vec![0], &mut rules, &options) {
+            SolveOutcome::BudgetExhausted {..} => {}
+            x => panic!("Expected budget to be exhausted, got {:?}", x),
+        }
+
+        let mut rules = |_cache: &HashSet<u32>, facts: &[u32]| {
+            Some(Inference::Propagate(facts.len() as u32))
+        };
+        let options = SolveOptions {max_facts: Some(3), ..Default::default()};
+        assert_eq!(solve(vec![0], &mut rules, &options).facts().len(), 4);
+    }
+
+    #[test]
+    fn fixpoint_and_cycle() {
+        let mut rules = |_cache: &HashSet<u32>, _facts: &[u32]| None;
+        assert_eq!(solve(vec![1], &mut rules, &SolveOptions::default()),
+                   SolveOutcome::Fixpoint(vec![1]));
+
+        let mut rules = alternate;
+        assert_eq!(solve(vec![2, 3], &mut rules, &SolveOptions::default()),
+                   SolveOutcome::Cycle(vec![1]));
+
+        let options = SolveOptions {cycle_detection: CycleDetection::Exact, ..Default::default()};
+        assert_eq!(solve(vec![2, 3], &mut rules, &options), SolveOutcome::Cycle(vec![1]));
+    }
+
+    #[test]
+    fn trace() {
+        let mut rules = alternate;
+        let (outcome, trace) = solve_traced(vec![2, 3], &mut rules, &SolveOptions::default());
+        assert_eq!(outcome, SolveOutcome::Cycle(vec![1]));
+        assert_eq!(trace.steps[0], TraceStep {
+            step: 0,
+            inference: Inference::Simplify {from: vec![2, 3], to: 1},
+            rule: None,
+            consumed: vec![2, 3],
+            produced: vec![1],
+        });
+        assert_eq!(trace.steps[1].consumed, vec![1]);
+        assert_eq!(trace.steps[1].produced, vec![2, 3]);
+        assert_eq!(trace.cycle_start, Some(2));
+        assert_eq!(trace.minimum, Some(3));
+    }
+
+    #[test]
+    fn simplify_one_missing() {
+        // Replaces a fact that does not exist once.
+        let mut fired = false;
+        let mut rules = |_cache: &HashSet<u32>, _facts: &[u32]| {
+            if fired {return None};
+            fired = true;
+            Some(Inference::SimplifyOne {from: 2, to: 9})
+        };
+        let options = SolveOptions {contradiction: Some(|x: &u32| *x == 9), ..Default::default()};
+        let (outcome, trace) = solve_traced(vec![1], &mut rules, &options);
+        assert_eq!(outcome, SolveOutcome::Fixpoint(vec![1]));
+        assert!(trace.steps[0].consumed.is_empty());
+        assert!(trace.steps[0].produced.is_empty());
+    }
+
+    #[test]
+    fn cycle() {
+        let mut rules = count_then_cycle;
+        let cycle = solve_cycle(vec![0], &mut rules, &SolveOptions::default()).unwrap();
+        assert_eq!(cycle.prefix, 1);
+        assert_eq!(cycle.states, vec![vec![1], vec![2], vec![3], vec![1, 2]]);
+        assert!(cycle.contains(&3));
+        assert!(!cycle.contains(&0));
+        assert_eq!(cycle.minimum(None), &[1]);
+        let measure = Measure::new(|facts: &[u32]| std::cmp::Reverse(facts.len()));
+        assert_eq!(cycle.minimum(Some(&measure)), &[1, 2]);
+
+        let mut rules = |_cache: &HashSet<u32>, _facts: &[u32]| None;
+        let cycle = solve_cycle(vec![0], &mut rules, &SolveOptions::default()).unwrap();
+        assert_eq!(cycle, Cycle {states: vec![vec![0]], prefix: 0});
+
+        // Depends on the order of facts, such that `[2, 1]` does not behave like `[1, 2]`.
+        let mut rules = |_cache: &HashSet<u32>, facts: &[u32]| {
+            match facts[0] {
+                1 => Some(Inference::SimplifyMany {from: vec![1, 2], to: vec![2, 1]}),
+                2 => Some(Inference::SimplifyOne {from: 2, to: 10}),
+                12 => Some(Inference::SimplifyOne {from: 12, to: 2}),
+                x => Some(Inference::SimplifyOne {from: x, to: x + 1}),
+            }
+        };
+        let options = SolveOptions {state_identity: StateIdentity::Multiset, ..Default::default()};
+        let cycle = solve_cycle(vec![1, 2], &mut rules, &options).unwrap();
+        assert_eq!(cycle.prefix, 1);
+        assert_eq!(cycle.states, vec![vec![2, 1], vec![10, 1], vec![11, 1], vec![12, 1]]);
+    }
+
+    #[test]
+    fn prove_goal() {
+        let mut rules = count_then_cycle;
+        assert_eq!(prove(vec![0], &[3], &mut rules), Some(Proof {state: vec![3], step: 3}));
+        assert_eq!(prove(vec![0], &[2, 1], &mut rules), Some(Proof {state: vec![1, 2], step: 4}));
+        assert_eq!(prove(vec![0], &[0], &mut rules), None);
+        assert_eq!(prove(vec![0], &[1, 1], &mut rules), None);
+    }
+
+    #[test]
+    fn contradiction() {
+        // Counts forever, where `3` means `false`.
+        let mut rules = |_cache: &HashSet<u32>, facts: &[u32]| {
+            Some(Inference::Propagate(facts.len() as u32))
+        };
+        let options = SolveOptions {contradiction: Some(|x: &u32| *x == 3), ..Default::default()};
+        let (outcome, trace) = solve_traced(vec![0], &mut rules, &options);
+        assert_eq!(outcome, SolveOutcome::Contradiction {facts: vec![0, 1, 2, 3], steps: 3});
+        assert_eq!(trace.steps[2].produced, vec![3]);
+        assert_eq!(solve(vec![3], &mut rules, &options),
+                   SolveOutcome::Contradiction {facts: vec![3], steps: 0});
+    }
+
+    #[test]
+    fn counts() {
+        #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+        enum Walk {Left, Right}
+
+        use Walk::*;
+
+        // Two steps left cancel one step right.
+        let mut rules = WithContext::new(|ctx| {
+            if ctx.count(&Left) >= 2 && ctx.contains(&Right) {
+                Some(Inference::ManyTrue {from: vec![Left, Left, Right]})
+            } else {None}
+        });
+        let res = solve_minimum_with(vec![Left, Right, Left, Left, Right, Left, Left], &mut rules);
+        assert_eq!(res, vec![Left]);
+
+        // Calling `infer` counts the facts.
+        let cache = vec![Left, Right].into_iter().collect();
+        assert_eq!(rules.infer(&cache, &[Left, Right, Left]),
+                   Some(Inference::ManyTrue {from: vec![Left, Left, Right]}));
+        assert_eq!(rules.infer(&cache, &[Left, Right]), None);
+    }
+
+    #[test]
+    fn index() {
+        #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+        enum Expr {Le(u32, u32), Eq(u32, u32)}
+
+        use Expr::*;
+
+        // Index `Le` by the left side.
+        let mut rules = Indexed::new(|x: &Expr| if let Le(a, _) = *x {Some(a)} else {None},
+            |index: &Index<u32, Expr>, ctx: &Context<Expr>| {
+                for ea in ctx.facts() {
+                    if let Le(a, b) = *ea {
+                        for eb in index.get(&b) {
+                            if let Le(_, c) = *eb {
+                                // (X <= Y) ∧ (Y <= Z) => (X <= Z)
+                                let new_expr = Le(a, c);
+                                if !ctx.contains(&new_expr) {return Some(Inference::Propagate(new_expr))}
+                            }
+                        }
+                    }
+                }
+                None
+            });
+        let res = solve_minimum_with(vec![Le(0, 1), Le(1, 2), Eq(3, 4)], &mut rules);
+        assert_eq!(res, vec![Le(0, 1), Le(1, 2), Eq(3, 4), Le(0, 2)]);
+        assert_eq!(rules.index.get(&1), &[Le(1, 2)]);
+        assert_eq!(rules.index.get(&3), &[]);
+    }
+
+    #[test]
+    fn rules_macro() {
+        rules! {
+            fn infer(cache: &HashSet<u32>, facts: &[u32]) {
+                // Remove zeroes.
+                [0] <=> [];
+                // Remove copies.
+                [a] / [b] <=> if a == b => [];
+                // Count up to `4`.
+                [a] ==> if *a < 4 => [a + 1];
+            }
+        }
+
+        assert_eq!(solve_minimum(vec![0, 2, 2, 0], infer), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn propagate_once() {
+        // Every `1` produces a `2`, while copies are legitimate.
+        let mut rules = WithContext::new(|ctx| {
+            for (i, fact) in ctx.facts().iter().enumerate() {
+                if *fact == 1 && !ctx.fired(0, &[i]) {
+                    return Some(Inference::PropagateOnce {rule: 0, heads: vec![i], to: vec![2]});
+                }
+            }
+            None
+        });
+        assert_eq!(solve_minimum_with(vec![1, 1], &mut rules), vec![1, 1, 2, 2]);
+
+        // The solver ignores propagations that already fired.
+        let mut rules = |_cache: &HashSet<u32>, _facts: &[u32]| {
+            Some(Inference::PropagateOnce {rule: 0, heads: vec![0], to: vec![2]})
+        };
+        assert_eq!(solve_minimum_with(vec![1], &mut rules), vec![1, 2]);
+    }
+
+    #[test]
+    fn simpagation() {
+        // `0` turns `1` into `2`.
+        let mut rules = |cache: &HashSet<u32>, _facts: &[u32]| {
+            if cache.contains(&1) {
+                Some(Inference::Simpagation {keep: vec![0], remove: vec![1], add: vec![2]})
+            } else {None}
+        };
+        let (outcome, trace) = solve_traced(vec![0, 1, 1], &mut rules, &SolveOptions::default());
+        assert_eq!(outcome, SolveOutcome::Fixpoint(vec![0, 2, 2]));
+        assert_eq!(trace.steps.len(), 2);
+        assert_eq!(trace.steps[0].consumed, vec![1]);
+        assert_eq!(trace.steps[0].produced, vec![2]);
+
+        // Nothing happens without the kept facts.
+        assert_eq!(solve_minimum_with(vec![1], &mut rules), vec![1]);
+
+        // A kept fact is not consumed.
+        let mut rules = |cache: &HashSet<u32>, _facts: &[u32]| {
+            if cache.contains(&1) {
+                Some(Inference::Simpagation {keep: vec![1], remove: vec![1], add: vec![2]})
+            } else {None}
+        };
+        assert_eq!(solve_minimum_with(vec![1], &mut rules), vec![1]);
+        assert_eq!(solve_minimum_with(vec![1, 1], &mut rules), vec![1, 2]);
+    }
+
+    #[test]
+    fn checked() {
+        // Removes `1` twice.
+        let mut rules = |_cache: &HashSet<u32>, _facts: &[u32]| {
+            Some(Inference::ManyTrue {from: vec![1, 1, 2]})
+        };
+        let options = SolveOptions {checked: true, ..Default::default()};
+        assert_eq!(solve(vec![1, 2], &mut rules, &options), SolveOutcome::Error(Box::new(RuleError {
+            inference: Inference::ManyTrue {from: vec![1, 1, 2]},
+            missing: vec![1],
+            facts: vec![1, 2],
+            steps: 0,
+        })));
+        // The first inference succeeds, the second fails.
+        match solve(vec![1, 1, 2], &mut rules, &options) {
+            SolveOutcome::Error(err) => {
+                assert_eq!(err.steps, 1);
+                assert_eq!(err.missing, vec![1, 1, 2]);
+            }
+            x => panic!("Expected error, got {:?}", x),
+        }
+
+        let mut rules = |_cache: &HashSet<u32>, _facts: &[u32]| {
+            Some(Inference::PropagateOnce {rule: 0, heads: vec![1], to: vec![2]})
+        };
+        match solve(vec![1], &mut rules, &options) {
+            SolveOutcome::Error(err) => assert!(err.missing.is_empty()),
+            x => panic!("Expected error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn counted() {
+        #[derive(Clone, PartialEq, Eq, Debug, Hash)]
+        enum Walk {Left, Right, Up}
+
+        use Walk::*;
+
+        // Cancels `Left` and `Right` one pair at a time.
+        let mut pairwise = |cache: &HashSet<Walk>, _facts: &[Walk]| {
+            if cache.contains(&Left) && cache.contains(&Right) {
+                Some(Inference::ManyTrue {from: vec![Left, Right]})
+            } else {None}
+        };
+        let mut start = vec![Up; 2];
+        start.extend(vec![Left; 300]);
+        start.extend(vec![Right; 200]);
+        let options = SolveOptions::default();
+        let res = solve_counted(start.clone(), &mut pairwise, &options);
+        assert_eq!(res, SolveOutcome::Fixpoint(solve(start.clone(), &mut pairwise, &options).into_facts()));
+
+        // Cancels all pairs at once.
+        let mut rules = WithContext::new(|ctx: &Context<Walk>| {
+            let n = ctx.count(&Left).min(ctx.count(&Right));
+            if n == 0 {return None};
+            Some(Inference::Counted {from: vec![(Left, n), (Right, n)], to: vec![]})
+        });
+        assert_eq!(solve_counted(start.clone(), &mut rules, &options), res);
+        assert_eq!(res.facts().len(), 102);
+        assert_eq!(solve(start, &mut rules, &options), res);
+
+        let options = SolveOptions {checked: true, ..Default::default()};
+        let mut rules = |_cache: &HashSet<Walk>, _facts: &[Walk]| {
+            Some(Inference::Counted {from: vec![(Up, 3)], to: vec![]})
+        };
+        match solve_counted(vec![Up, Up], &mut rules, &options) {
+            SolveOutcome::Error(err) => assert_eq!(err.missing, vec![Up]),
+            x => panic!("Expected error, got {:?}", x),
+        }
+
+        // Every `1` produces a `2`, which fires once for all copies of `1`.
+        let mut rules = WithContext::new(|ctx: &Context<u32>| {
+            for (i, fact) in ctx.facts().iter().enumerate() {
+                if *fact == 1 && !ctx.fired(0, &[i]) {
+                    return Some(Inference::PropagateOnce {rule: 0, heads: vec![i], to: vec![2]});
+                }
+            }
+            None
+        });
+        let options = SolveOptions::default();
+        assert_eq!(solve_counted(vec![1, 1], &mut rules, &options), SolveOutcome::Fixpoint(vec![1, 1, 2]));
+        assert_eq!(solve(vec![1, 1], &mut rules, &options), SolveOutcome::Fixpoint(vec![1, 1, 2, 2]));
+
+        // Copies of a fact are next to each other.
+        let mut rules = |_cache: &HashSet<u32>, _facts: &[u32]| None;
+        assert_eq!(solve_counted(vec![1, 2, 1], &mut rules, &options), SolveOutcome::Fixpoint(vec![1, 1, 2]));
+
+        // Counts to `3` copies of `1` and then starts over,
+        // which is a cycle of three states with the same distinct facts.
+        let mut rules = WithContext::new(|ctx: &Context<u32>| {
+            if ctx.count(&1) < 3 {
+                Some(Inference::Propagate(1))
+            } else {
+                Some(Inference::Counted {from: vec![(1, 2)], to: vec![]})
+            }
+        });
+        for &cycle_detection in &[CycleDetection::Probabilistic, CycleDetection::Exact] {
+            let options = SolveOptions {cycle_detection, ..Default::default()};
+            assert_eq!(solve_counted(vec![1], &mut rules, &options), SolveOutcome::Cycle(vec![1]));
+            let measure = Measure::new(|facts: &[u32]| std::cmp::Reverse(facts.len()));
+            let options = SolveOptions {measure: Some(measure), ..options};
+            assert_eq!(solve_counted(vec![1], &mut rules, &options), SolveOutcome::Cycle(vec![1, 1, 1]));
+        }
+    }
+
+    #[test]
+    fn multiple_histories() {
+        // Either increments or doubles a number below 10.
+        let mut rules = Choices::new(|ctx: &Context<u32>| {
+            let x = ctx.facts()[0];
+            if x >= 10 {return vec![]};
+            vec![
+                Inference::SimplifyOne {from: x, to: x + 1},
+                Inference::SimplifyOne {from: x, to: 2 * x},
+            ]
+        });
+        for &search in &[Search::DepthFirst, Search::BreadthFirst, Search::BestFirst] {
+            let options = SolveOptions {search, ..Default::default()};
+            let mut res: Vec<u32> = solve_all(vec![1], &mut rules, &options).into_iter()
+                .map(|outcome| outcome.into_facts()[0]).collect();
+            res.sort();
+            assert_eq!(res, vec![10, 12, 14, 16, 18]);
+            assert_eq!(solve_goal(vec![1], &[16], &mut rules, &options),
+                       Some(SolveOutcome::Fixpoint(vec![16])));
+            assert_eq!(solve_goal(vec![1], &[20], &mut rules, &options), None);
+        }
+
+        // Either returns from `2` to `1` or ends in `3`.
+        let mut rules = Choices::new(|ctx: &Context<u32>| {
+            match ctx.facts()[0] {
+                1 => vec![Inference::SimplifyOne {from: 1, to: 2}],
+                2 => vec![
+                    Inference::SimplifyOne {from: 2, to: 1},
+                    Inference::SimplifyOne {from: 2, to: 3},
+                ],
+                _ => vec![],
+            }
+        });
+        assert_eq!(solve_all(vec![1], &mut rules, &SolveOptions::default()),
+                   vec![SolveOutcome::Cycle(vec![1]), SolveOutcome::Fixpoint(vec![3])]);
+        // Only the first alternative is used when solving a single history.
+        assert_eq!(solve(vec![1], &mut rules, &SolveOptions::default()),
+                   SolveOutcome::Cycle(vec![1]));
+
+        // Two histories enter the cycle between `4` and `5` at different points.
+        let mut rules = Choices::new(|ctx: &Context<u32>| {
+            let to: Vec<u32> = match ctx.facts()[0] {
+                0 => vec![1, 2],
+                1 => vec![3],
+                2 | 4 => vec![5],
+                3 | 5 => vec![4],
+                _ => vec![],
+            };
+            let from = ctx.facts()[0];
+            to.into_iter().map(|to| Inference::SimplifyOne {from, to}).collect()
+        });
+        assert_eq!(solve_all(vec![0], &mut rules, &SolveOptions::default()), vec![SolveOutcome::Cycle(vec![4])]);
+        for &search in &[Search::DepthFirst, Search::BreadthFirst, Search::BestFirst] {
+            let options = SolveOptions {search, ..Default::default()};
+            let res = solve_all(vec![0], &mut rules, &options);
+            assert!(res == [SolveOutcome::Cycle(vec![4])] || res == [SolveOutcome::Cycle(vec![5])]);
+            assert!(solve_goal(vec![0], &[4], &mut rules, &options).is_some());
+            assert!(solve_goal(vec![0], &[5], &mut rules, &options).is_some());
+        }
+
+        // Removes any fact, such that every subset is reached in many ways.
+        let mut calls = 0;
+        for &search in &[Search::DepthFirst, Search::BreadthFirst, Search::BestFirst] {
+            let options = SolveOptions {search, state_identity: StateIdentity::Multiset, ..Default::default()};
+            let mut rules = Choices::new(|ctx: &Context<u32>| {
+                calls += 1;
+                ctx.facts().iter().map(|&from| Inference::OneTrue {from}).collect()
+            });
+            assert_eq!(solve_all((1..10).collect(), &mut rules, &options), vec![SolveOutcome::Fixpoint(vec![])]);
+            // Every subset is explored once.
+            assert_eq!(calls, 512);
+            calls = 0;
+        }
+    }
+
+    #[test]
+    fn split_cases() {
+        let mut branches = 0;
+        let mut rules = |cache: &HashSet<u32>, facts: &[u32]| {
+            if facts == [1] {branches += 1};
+            if cache.contains(&0) {
+                return Some(Inference::Split {from: 0, cases: vec![1, 2, 3, 4, 1]});
+            }
+            // Even numbers are contradictions.
+            if cache.contains(&2) || cache.contains(&4) {
+                return Some(Inference::Propagate(100));
+            }
+            None
+        };
+        let options = SolveOptions {contradiction: Some(|n| *n == 100), ..Default::default()};
+        assert_eq!(solve(vec![0], &mut rules, &options), SolveOutcome::Fixpoint(vec![1, 3, 1]));
+        // The same branch is solved once.
+        assert_eq!(branches, 1);
+
+        // No case survives.
+        let mut rules = |cache: &HashSet<u32>, _facts: &[u32]| {
+            if cache.contains(&0) {
+                return Some(Inference::Split {from: 0, cases: vec![2, 4]});
+            }
+            if cache.contains(&2) || cache.contains(&4) {
+                return Some(Inference::Propagate(100));
+            }
+            None
+        };
+        assert_eq!(solve(vec![0, 5], &mut rules, &options),
+                   SolveOutcome::Contradiction {facts: vec![0, 5], steps: 1});
+        assert_eq!(solve_all(vec![0, 5], &mut rules, &options),
+                   vec![SolveOutcome::Contradiction {facts: vec![0, 5], steps: 1}]);
+    }
+
+    #[test]
+    fn parallel() {
+        // Even numbers are contradictions.
+        let rules = |cache: &HashSet<u32>, _facts: &[u32]| {
+            if cache.contains(&0) {
+                return Some(Inference::Split {from: 0, cases: (1..20).collect()});
+            }
+            for x in cache {
+                if x % 2 == 0 && *x < 100 {return Some(Inference::Propagate(100))}
+            }
+            None
+        };
+        let options = SolveOptions {
+            contradiction: Some(|n| *n == 100),
+            cycle_detection: CycleDetection::Exact,
+            ..Default::default()
+        };
+        assert_eq!(solve_parallel(vec![0], &mut rules.clone(), &options),
+                   solve(vec![0], &mut rules.clone(), &options));
+
+        let outcomes = solve_branches((1..20).map(|x| vec![x]).collect(), &rules, &options);
+        assert_eq!(outcomes.len(), 19);
+        for (x, outcome) in (1..20).zip(outcomes) {
+            if x % 2 == 0 {
+                assert_eq!(outcome, SolveOutcome::Contradiction {facts: vec![x, 100], steps: 1});
+            } else {
+                assert_eq!(outcome, SolveOutcome::Fixpoint(vec![x]));
+            }
+        }
+
+        // Wrapped rules can be cloned for every thread.
+        let mut wrapped = WithContext::new(move |ctx: &Context<u32>| rules(ctx.cache(), ctx.facts()));
+        assert_eq!(solve_parallel(vec![0], &mut wrapped, &options),
+                   solve(vec![0], &mut rules.clone(), &options));
+        // Shared rule sets and tactics can be cloned for every thread.
+        let mut rule_set = RuleSet::shared();
+        rule_set.add("even", RuleKind::Propagation, 0, move |ctx: &Context<u32>| rules(ctx.cache(), ctx.facts()));
+        let mut tactics = Tactics::shared();
+        tactics.add("even", rule_set.clone());
+        assert_eq!(solve_parallel(vec![0], &mut rule_set, &options),
+                   solve(vec![0], &mut rules.clone(), &options));
+        assert_eq!(solve_parallel(vec![0], &mut tactics, &options),
+                   solve(vec![0], &mut rules.clone(), &options));
+    }
+
+    #[test]
+    fn session() {
+        // Cancels `1` and `2`.
+        let mut rules = |cache: &HashSet<u32>, _facts: &[u32]| {
+            if cache.contains(&1) && cache.contains(&2) {
+                Some(Inference::ManyTrue {from: vec![1, 2]})
+            } else {None}
+        };
+        let mut solver = Solver::new(vec![1, 1, 2], SolveOptions::default());
+        assert_eq!(solver.run(&mut rules), SolveOutcome::Fixpoint(vec![1]));
+        solver.add_facts(vec![3, 2]);
+        assert_eq!(solver.facts(), &[1, 3, 2]);
+        assert_eq!(solver.run(&mut rules), SolveOutcome::Fixpoint(vec![3]));
+        assert_eq!(solver.steps(), 2);
+        assert!(solver.retract(&3));
+        assert!(!solver.retract(&3));
+        assert_eq!(solver.run(&mut rules), SolveOutcome::Fixpoint(vec![]));
+
+        // `1` produces `9` once, while `5` alternates with `6, 7`.
+        let mut rules = WithContext::new(|ctx: &Context<u32>| {
+            if let Some(i) = ctx.facts().iter().position(|&n| n == 1) {
+                if !ctx.fired(0, &[i]) {
+                    return Some(Inference::PropagateOnce {rule: 0, heads: vec![i], to: vec![9]});
+                }
+            }
+            if ctx.contains(&5) {
+                Some(Inference::SimplifyMany {from: vec![5], to: vec![6, 7]})
+            } else {
+                Some(Inference::Simplify {from: vec![6, 7], to: 5})
+            }
+        });
+        let mut solver = Solver::new(vec![1, 5], SolveOptions::default());
+        assert_eq!(solver.run(&mut rules), SolveOutcome::Cycle(vec![1, 9, 5]));
+        solver.add_facts(vec![3]);
+        assert_eq!(solver.run(&mut rules), SolveOutcome::Cycle(vec![1, 9, 3, 5]));
+    }
+
+    #[test]
+    fn step_iterator() {
+        let mut rules = alternate;
+        let mut solver = Solver::new(vec![2, 3], SolveOptions::default());
+        let mut iter = solver.iter_steps(&mut rules);
+        let steps: Vec<Step<u32>> = iter.by_ref().collect();
+        assert_eq!(iter.outcome(), Some(&SolveOutcome::Cycle(vec![1])));
+        assert_eq!(steps.len(), 4);
+        assert_eq!(steps[0], Step {
+            step: 0,
+            inference: Inference::Simplify {from: vec![2, 3], to: 1},
+            rule: None,
+            facts: vec![1],
+            detection: Detection::Solving,
+        });
+        assert_eq!(steps[2].detection, Detection::SearchMinimum(vec![2, 3]));
+        assert_eq!(steps[3].detection, Detection::SearchMinimum(vec![1]));
+        assert_eq!(solver.facts(), &[1]);
+    }
+
+    #[test]
+    fn rule_set() {
+        let mut rules = RuleSet::new();
+        rules.add("double", RuleKind::Propagation, 0, |ctx: &Context<u32>| {
+            let x = *ctx.facts().iter().max()?;
+            if x < 8 {Some(Inference::Propagate(2 * x))} else {None}
+        });
+        rules.add("remove_one", RuleKind::Simplification, 0, |ctx: &Context<u32>| {
+            if ctx.contains(&1) {Some(Inference::OneTrue {from: 1})} else {None}
+        });
+        rules.add("remove_two", RuleKind::Simplification, 1, |ctx: &Context<u32>| {
+            if ctx.contains(&2) {Some(Inference::OneTrue {from: 2})} else {None}
+        });
+        assert_eq!(rules.names(), vec!["remove_two", "remove_one", "double"]);
+
+        let (outcome, trace) = solve_traced(vec![1, 2], &mut rules, &SolveOptions::default());
+        assert_eq!(outcome, SolveOutcome::Fixpoint(vec![]));
+        let fired: Vec<_> = trace.steps.iter().map(|step| step.rule.as_deref().unwrap()).collect();
+        assert_eq!(fired, vec!["remove_two", "remove_one"]);
+
+        let (outcome, trace) = solve_traced(vec![3], &mut rules, &SolveOptions::default());
+        assert_eq!(outcome, SolveOutcome::Fixpoint(vec![3, 6, 12]));
+        assert_eq!(trace.steps[0].rule.as_deref(), Some("double"));
+    }
+
+    #[test]
+    fn tactics() {
+        let mut tactics = Tactics::new();
+        tactics.add("shrink", |_: &HashSet<u32>, facts: &[u32]| {
+            let x = *facts.iter().find(|&&n| n < 4)?;
+            Some(Inference::OneTrue {from: x})
+        });
+        tactics.add("grow", |_: &HashSet<u32>, facts: &[u32]| {
+            let x = *facts.iter().max()?;
+            if x < 8 {Some(Inference::Propagate(2 * x))} else {None}
+        });
+        assert_eq!(tactics.names(), vec!["shrink", "grow"]);
+
+        let (outcome, trace) = solve_traced(vec![1], &mut tactics, &SolveOptions::default());
+        assert_eq!(outcome, SolveOutcome::Fixpoint(vec![]));
+        assert_eq!(trace.steps[0].rule.as_deref(), Some("shrink"));
+
+        assert!(tactics.disable("shrink"));
+        assert!(!tactics.disable("unknown"));
+        assert_eq!(solve_minimum_with(vec![1], &mut tactics), vec![1, 2, 4, 8]);
+        assert!(tactics.enable("shrink"));
+
+        // Grow to fixpoint before shrinking.
+        let mut solver = Solver::new(vec![1], SolveOptions::default());
+        let mut facts = tactics.sequence(&mut solver, &[&["grow"], &["shrink"]]).into_facts();
+        facts.sort();
+        assert_eq!(facts, vec![4, 8]);
+        assert!(tactics.is_enabled("shrink") && tactics.is_enabled("grow"));
+    }
+
+    #[test]
+    fn phases() {
+        fn swap(_: &HashSet<u32>, facts: &[u32]) -> Option<Inference<u32>> {
+            match facts[0] {
+                1 => Some(Inference::SimplifyOne {from: 1, to: 2}),
+                2 => Some(Inference::SimplifyOne {from: 2, to: 1}),
+                _ => None,
+            }
+        }
+        fn finish(cache: &HashSet<u32>, _: &[u32]) -> Option<Inference<u32>> {
+            if cache.contains(&1) {Some(Inference::SimplifyOne {from: 1, to: 3})} else {None}
+        }
+
+        // The second phase starts in a state seen by the first phase.
+        assert_eq!(solve_minimum_phases(vec![1], &[swap, finish]), vec![3]);
+
+        let (mut grow, mut shrink) = (
+            |_: &HashSet<u32>, facts: &[u32]| {
+                let x = *facts.iter().max()?;
+                if x < 8 {Some(Inference::Propagate(2 * x))} else {None}
+            },
+            |_: &HashSet<u32>, facts: &[u32]| {
+                let x = *facts.iter().find(|&&n| n < 4)?;
+                Some(Inference::OneTrue {from: x})
+            },
+        );
+        let outcome = solve_phases(vec![1], &mut [&mut grow, &mut shrink], &SolveOptions::default());
+        assert_eq!(outcome, SolveOutcome::Fixpoint(vec![8, 4]));
+
+        // Branches of splits are solved again with the rules of the next phase.
+        let (mut expand, mut check) = (
+            |cache: &HashSet<u32>, _: &[u32]| {
+                if cache.contains(&0) {Some(Inference::Split {from: 0, cases: vec![2, 3]})} else {None}
+            },
+            |cache: &HashSet<u32>, _: &[u32]| {
+                if cache.contains(&0) {return Some(Inference::Split {from: 0, cases: vec![2, 3]})};
+                if cache.contains(&2) && cache.contains(&3) {
+                    return Some(Inference::SimplifyMany {from: vec![2, 3], to: vec![0]});
+                }
+                // `2` is a contradiction.
+                if cache.contains(&2) {Some(Inference::Propagate(100))} else {None}
+            },
+        );
+        let options = SolveOptions {contradiction: Some(|n| *n == 100), ..Default::default()};
+        let outcome = solve_phases(vec![0], &mut [&mut expand, &mut check], &options);
+        assert_eq!(outcome, SolveOutcome::Fixpoint(vec![3]));
+        let mut solver = Solver::new(vec![0], options.clone());
+        assert_eq!(solver.run(&mut expand), SolveOutcome::Fixpoint(vec![2, 3]));
+        assert_eq!(solver.run(&mut check), SolveOutcome::Fixpoint(vec![3]));
+    }
+
+    #[test]
+    fn termination() {
+        let mut rules = |_: &HashSet<u32>, facts: &[u32]| {
+            let x = facts[0];
+            let to = if x < 3 {(x + 1) % 3} else if x < 5 {x + 1} else {return None};
+            Some(Inference::SimplifyOne {from: x, to})
+        };
+        let res = solve_minimum_report(vec![0], &mut rules);
+        assert_eq!(res, Minimum {facts: vec![0], termination: Termination::Cycle {length: 3}, steps: 6});
+        let res = solve_minimum_report(vec![3], &mut rules);
+        assert_eq!(res, Minimum {facts: vec![5], termination: Termination::Fixpoint, steps: 2});
+
+        // A split without cases is a contradiction, even without `SolveOptions::contradiction`.
+        let mut rules = |cache: &HashSet<u32>, _: &[u32]| {
+            if cache.contains(&0) {Some(Inference::Split {from: 0, cases: vec![]})} else {None}
+        };
+        let res = solve_minimum_report(vec![0, 1], &mut rules);
+        assert_eq!(res, Minimum {facts: vec![0, 1], termination: Termination::Contradiction, steps: 1});
+    }
+
+    #[test]
+    fn measure() {
+        let mut rules = |_: &HashSet<u32>, facts: &[u32]| {
+            Some(Inference::SimplifyOne {from: facts[0], to: (facts[0] + 1) % 3})
+        };
+        assert_eq!(solve_minimum_with(vec![1], &mut rules), vec![1]);
+
+        let options = SolveOptions {
+            measure: Some(Measure::new(|facts: &[u32]| std::cmp::Reverse(facts[0]))),
+            ..Default::default()
+        };
+        assert_eq!(solve(vec![0], &mut rules, &options), SolveOutcome::Cycle(vec![2]));
+        assert_eq!(solve_counted(vec![0], &mut rules, &options), SolveOutcome::Cycle(vec![2]));
+
+        // Ties do not depend on where the cycle was entered.
+        let options = SolveOptions {measure: Some(Measure::new(|_: &[u32]| 0)), ..Default::default()};
+        let res: Vec<_> = (0..3).map(|x| solve(vec![x], &mut rules, &options)).collect();
+        assert!(res[0] == res[1] && res[1] == res[2]);
+
+        // Best first search explores the simplest set of facts first.
+        let mut rules = Choices::new(|ctx: &Context<u32>| {
+            if ctx.facts() != [0] {return vec![]};
+            vec![Inference::SimplifyOne {from: 0, to: 1}, Inference::SimplifyOne {from: 0, to: 2}]
+        });
+        let options = SolveOptions {search: Search::BestFirst, ..Default::default()};
+        assert_eq!(solve_all(vec![0], &mut rules, &options),
+                   vec![SolveOutcome::Fixpoint(vec![1]), SolveOutcome::Fixpoint(vec![2])]);
+        let options = SolveOptions {
+            search: Search::BestFirst,
+            measure: Some(Measure::new(|facts: &[u32]| std::cmp::Reverse(facts[0]))),
+            ..Default::default()
+        };
+        assert_eq!(solve_all(vec![0], &mut rules, &options),
+                   vec![SolveOutcome::Fixpoint(vec![2]), SolveOutcome::Fixpoint(vec![1])]);
+    }
+
+    #[test]
+    fn multiset_identity() {
+        // Rotates facts without changing the multiset.
+        let mut rules = |_cache: &HashSet<u32>, facts: &[u32]| {
+            Some(Inference::SimplifyMany {from: vec![facts[0]], to: vec![facts[0]]})
+        };
+        for &cycle_detection in &[CycleDetection::Probabilistic, CycleDetection::Exact] {
+            let options = SolveOptions {
+                cycle_detection,
+                state_identity: StateIdentity::Multiset,
+                ..Default::default()
+            };
+            match solve(vec![1, 2, 3, 4], &mut rules, &options) {
+                SolveOutcome::Cycle(_) => {}
+                x => panic!("Expected cycle, got {:?}", x),
+            }
+        }
     }
 }
@@ -0,0 +1,161 @@
+//! Declarative rules in the style of Constraint Handling Rules.
+
+/// Generates an `infer` function from rules in the style of
+/// [CHR (Constraint Handling Rules)](https://dtai.cs.kuleuven.be/CHR/).
+///
+/// The generated function has the same signature as the `infer` function
+/// passed to `solve_minimum`.
+///
+/// Each rule consists of heads, which are Rust patterns matched against facts,
+/// an optional guard `if <expr> =>` and a body, which is a list of new facts.
+/// Rules are tried in order, so put simplification rules first
+/// to find the simplest set of facts.
+///
+/// - Simplification `[H..] <=> [B..];` removes the heads and adds the body
+/// - Propagation `[H..] ==> [B..];` keeps the heads and adds the body
+/// - Simpagation `[H1..] / [H2..] <=> [B..];` keeps `H1`, removes `H2` and adds the body
+///
+/// Since `\` is not a Rust token, simpagation uses `/` to separate heads.
+///
+/// Every head matches a different fact.
+/// Patterns bind by reference, so clone bound values when using them in the body.
+/// A propagation only adds facts that do not exist,
+/// and it does not fire when all facts in the body exist.
+///
+/// ```rust
+/// #[macro_use]
+/// extern crate linear_solver;
+///
+/// use linear_solver::solve_minimum;
+///
+/// use std::collections::HashSet;
+///
+/// use self::Expr::*;
+///
+/// #[derive(Clone, PartialEq, Eq, Debug, Hash)]
+/// pub enum Expr {
+///     Var(&'static str),
+///     Le(Box<Expr>, Box<Expr>),
+///     Eq(Box<Expr>, Box<Expr>),
+/// }
+///
+/// rules! {
+///     /// Infers equality from less or equal.
+///     pub fn infer(cache: &HashSet<Expr>, facts: &[Expr]) {
+///         // (X <= X) <=> true
+///         [Le(a, b)] <=> if a == b => [];
+///         // (X <= Y) ∧ (Y <= X) <=> (X = Y)
+///         [Le(a, b), Le(c, d)] <=> if a == d && b == c => [Eq(a.clone(), b.clone())];
+///         // (X = Y) \ (Y <= Z) <=> (X <= Z)
+///         [Eq(a, b)] / [Le(c, d)] <=> if c == b => [Le(a.clone(), d.clone())];
+///         // (X <= Y) ∧ (Y <= Z) ==> (X <= Z)
+///         [Le(a, b), Le(c, d)] ==> if b == c => [Le(a.clone(), d.clone())];
+///     }
+/// }
+///
+/// pub fn var(name: &'static str) -> Box<Expr> {Box::new(Var(name))}
+///
+/// fn main() {
+///     let start = vec![
+///         Le(var("X"), var("Y")), // X <= Y
+///         Le(var("Y"), var("X")), // Y <= X
+///     ];
+///
+///     let res = solve_minimum(start, infer);
+///     assert_eq!(res, vec![Eq(var("X"), var("Y"))]);
+/// }
+/// ```
+#[macro_export]
+macro_rules! rules {
+    (
+        $(#[$attr:meta])*
+        $vis:vis fn $name:ident($cache:ident : &HashSet<$t:ty>, $facts:ident : &[$t2:ty]) {
+            $($rules:tt)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[allow(irrefutable_let_patterns, unused_variables)]
+        $vis fn $name(
+            $cache: &::std::collections::HashSet<$t>,
+            $facts: &[$t2]
+        ) -> ::std::option::Option<$crate::Inference<$t>> {
+            let _ = $cache;
+            $crate::rules!(@rules ($cache, $facts) $($rules)*);
+            ::std::option::Option::None
+        }
+    };
+    (@rules $cx:tt) => {};
+    // Simpagation.
+    (@rules $cx:tt [$($k:pat),+] / [$($r:pat),+] <=> $(if $g:expr =>)? [$($b:expr),*];
+     $($rest:tt)*) => {
+        $crate::rules!(@keep $cx [] [$($k),+] [$($r),+] (simpagation [$($g)?] [$($b),*]));
+        $crate::rules!(@rules $cx $($rest)*);
+    };
+    // Simplification.
+    (@rules $cx:tt [$($r:pat),+] <=> $(if $g:expr =>)? [$($b:expr),*]; $($rest:tt)*) => {
+        $crate::rules!(@keep $cx [] [] [$($r),+] (simplify [$($g)?] [$($b),*]));
+        $crate::rules!(@rules $cx $($rest)*);
+    };
+    // Propagation.
+    (@rules $cx:tt [$($k:pat),+] ==> $(if $g:expr =>)? [$($b:expr),*]; $($rest:tt)*) => {
+        $crate::rules!(@keep $cx [] [$($k),+] [] (propagate [$($g)?] [$($b),*]));
+        $crate::rules!(@rules $cx $($rest)*);
+    };
+    // Match heads that are kept.
+    (@keep ($cache:ident, $facts:ident) [$($k:ident),*] [$p:pat $(, $ps:pat)*] $rs:tt $act:tt) => {
+        for (i, fact) in $facts.iter().enumerate() {
+            if $(i == $k ||)* false {continue}
+            if let $p = fact {
+                $crate::rules!(@keep ($cache, $facts) [$($k,)* i] [$($ps),*] $rs $act);
+            }
+        }
+    };
+    (@keep $cx:tt $ks:tt [] [$($rs:pat),*] $act:tt) => {
+        $crate::rules!(@remove $cx $ks [] [$($rs),*] $act);
+    };
+    // Match heads that are removed.
+    (@remove ($cache:ident, $facts:ident) [$($k:ident),*] [$($r:ident),*] [$p:pat $(, $ps:pat)*]
+     $act:tt) => {
+        for (i, fact) in $facts.iter().enumerate() {
+            if $(i == $k ||)* $(i == $r ||)* false {continue}
+            if let $p = fact {
+                $crate::rules!(@remove ($cache, $facts) [$($k),*] [$($r,)* i] [$($ps),*] $act);
+            }
+        }
+    };
+    (@remove ($cache:ident, $facts:ident) $ks:tt [$($r:ident),*] []
+     (simplify [$($g:expr)?] [$($b:expr),*])) => {
+        if $crate::rules!(@guard $($g)?) {
+            return ::std::option::Option::Some($crate::Inference::SimplifyMany {
+                from: vec![$($facts[$r].clone()),*],
+                to: vec![$($b),*],
+            });
+        }
+    };
+    (@remove ($cache:ident, $facts:ident) [$($k:ident),*] [$($r:ident),*] []
+     (simpagation [$($g:expr)?] [$($b:expr),*])) => {
+        if $crate::rules!(@guard $($g)?) {
+            return ::std::option::Option::Some($crate::Inference::Simpagation {
+                keep: vec![$($facts[$k].clone()),*],
+                remove: vec![$($facts[$r].clone()),*],
+                add: vec![$($b),*],
+            });
+        }
+    };
+    (@remove ($cache:ident, $facts:ident) $ks:tt [] [] (propagate [$($g:expr)?] [$($b:expr),*])) => {
+        if $crate::rules!(@guard $($g)?) {
+            let mut to: Vec<_> = vec![$($b),*];
+            to.retain(|n| !$cache.contains(n));
+            if to.len() == 1 {
+                return ::std::option::Option::Some($crate::Inference::Propagate(to.pop().unwrap()));
+            } else if !to.is_empty() {
+                return ::std::option::Option::Some($crate::Inference::SimplifyMany {
+                    from: vec![],
+                    to,
+                });
+            }
+        }
+    };
+    (@guard) => {true};
+    (@guard $g:expr) => {$g};
+}
@@ -0,0 +1,103 @@
+//! Solving branches on multiple threads.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use super::{Rules, SolveOptions, SolveOutcome, Solver};
+
+/// Rules that can be cloned behind a box, such that they can be solved on other threads.
+///
+/// Implemented for all rules that are `Clone + Send`.
+/// Used by `RuleSet::shared` and `Tactics::shared`.
+pub trait SharedRules<T>: Rules<T> + Send {
+    /// Clones the rules into a new box.
+    fn clone_box(&self) -> Box<dyn SharedRules<T>>;
+}
+
+impl<T, R> SharedRules<T> for R
+    where R: Rules<T> + Clone + Send + 'static
+{
+    fn clone_box(&self) -> Box<dyn SharedRules<T>> {Box::new(self.clone())}
+}
+
+impl<T> Clone for Box<dyn SharedRules<T>> {
+    fn clone(&self) -> Box<dyn SharedRules<T>> {self.clone_box()}
+}
+
+/// Solves like `solve`, but solves the branches of `Inference::Split` on multiple threads.
+///
+/// Every thread uses its own clone of the rules,
+/// so changes to the state of the rules while solving branches are not kept.
+/// Splits inside branches are solved on the thread of the branch.
+/// The result does not depend on how threads are scheduled.
+pub fn solve_parallel<T, R>(facts: Vec<T>, rules: &mut R, options: &SolveOptions<T>) -> SolveOutcome<T>
+    where T: Clone + Eq + Hash + Send + Sync, R: Rules<T> + Clone + Send
+{
+    Solver::new(facts, options.clone()).run_with(rules, &mut |rules: &mut R, branches, memo| {
+        parallel(branches, rules, options, memo).into_iter()
+            .map(|outcome| !matches!(outcome, SolveOutcome::Contradiction {..}))
+            .collect()
+    })
+}
+
+/// Solves every list of starting facts on multiple threads.
+///
+/// This is useful for exploring independent alternatives concurrently.
+/// Every thread uses its own clone of the rules.
+/// Returns the outcomes in the same order as the branches,
+/// independent of how threads are scheduled.
+pub fn solve_branches<T, R>(
+    branches: Vec<Vec<T>>,
+    rules: &R,
+    options: &SolveOptions<T>
+) -> Vec<SolveOutcome<T>>
+    where T: Clone + Eq + Hash + Send + Sync, R: Rules<T> + Clone + Send
+{
+    parallel(branches, rules, options, &mut HashMap::new())
+}
+
+// Solves branches using one thread per available core.
+// Results of splits are shared through `memo` before and after solving.
+fn parallel<T, R>(
+    branches: Vec<Vec<T>>,
+    rules: &R,
+    options: &SolveOptions<T>,
+    memo: &mut HashMap<Vec<T>, bool>
+) -> Vec<SolveOutcome<T>>
+    where T: Clone + Eq + Hash + Send + Sync, R: Rules<T> + Clone + Send
+{
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(branches.len());
+    // The index of the next branch to solve.
+    let next = AtomicUsize::new(0);
+    let mut outcomes: Vec<Option<SolveOutcome<T>>> = branches.iter().map(|_| None).collect();
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|_| {
+            let mut rules = rules.clone();
+            let mut memo = memo.clone();
+            let (next, branches) = (&next, &branches);
+            scope.spawn(move || {
+                let mut res = vec![];
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= branches.len() {break};
+                    let mut solver = Solver::new(branches[i].clone(), options.clone());
+                    solver.splits = memo;
+                    res.push((i, solver.run_rules(&mut rules)));
+                    memo = solver.splits;
+                }
+                (res, memo)
+            })
+        }).collect();
+        for handle in handles {
+            let (res, splits) = handle.join().unwrap_or_else(|err| panic::resume_unwind(err));
+            for (i, outcome) in res {
+                outcomes[i] = Some(outcome);
+            }
+            memo.extend(splits);
+        }
+    });
+    outcomes.into_iter().map(|outcome| outcome.unwrap()).collect()
+}
@@ -0,0 +1,130 @@
+//! Named rules with priorities.
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use super::{infer_from_facts, Context, Inference, Rules, SharedRules, WithContext};
+
+/// Tells whether a rule removes facts or only adds new ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RuleKind {
+    /// Removes or replaces facts.
+    Simplification,
+    /// Adds new facts while keeping the old ones.
+    Propagation,
+}
+
+/// A set of named rules, tried in order of kind and priority.
+///
+/// Simplification rules are tried before propagation rules,
+/// such that the solver finds the simplest set of facts.
+/// Among rules of the same kind, rules with higher priority are tried first.
+/// Rules with the same priority are tried in the order they were added.
+///
+/// The name of the rule that fired is reported by `Rules::fired_rule`,
+/// which the solver records in `TraceStep` and `Step`.
+///
+/// To use a rule set with `solve_parallel`, create it with `RuleSet::shared`.
+pub struct RuleSet<T, R: ?Sized = dyn Rules<T>> {
+    rules: Vec<NamedRule<R>>,
+    // The index of the rule that fired last.
+    fired: Option<usize>,
+    marker: PhantomData<T>,
+}
+
+struct NamedRule<R: ?Sized> {
+    name: String,
+    kind: RuleKind,
+    priority: i32,
+    rules: Box<R>,
+}
+
+impl<T> Clone for RuleSet<T, dyn SharedRules<T>> {
+    fn clone(&self) -> Self {
+        RuleSet {
+            rules: self.rules.iter().map(|rule| NamedRule {
+                name: rule.name.clone(),
+                kind: rule.kind,
+                priority: rule.priority,
+                rules: rule.rules.clone(),
+            }).collect(),
+            fired: self.fired,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, R: ?Sized> Default for RuleSet<T, R> {
+    fn default() -> RuleSet<T, R> {
+        RuleSet {rules: vec![], fired: None, marker: PhantomData}
+    }
+}
+
+impl<T> RuleSet<T> {
+    /// Creates an empty rule set.
+    pub fn new() -> RuleSet<T> {RuleSet::default()}
+}
+
+impl<T: Clone + Eq + Hash> RuleSet<T> {
+    /// Adds a named rule.
+    pub fn add<F>(&mut self, name: &str, kind: RuleKind, priority: i32, infer: F)
+        where F: FnMut(&Context<T>) -> Option<Inference<T>> + 'static
+    {
+        self.push(name, kind, priority, Box::new(WithContext(infer)));
+    }
+}
+
+impl<T> RuleSet<T, dyn SharedRules<T>> {
+    /// Creates an empty rule set that can be cloned for other threads.
+    ///
+    /// Rules added to it must be `Clone + Send`.
+    pub fn shared() -> RuleSet<T, dyn SharedRules<T>> {RuleSet::default()}
+}
+
+impl<T: Clone + Eq + Hash> RuleSet<T, dyn SharedRules<T>> {
+    /// Adds a named rule.
+    pub fn add<F>(&mut self, name: &str, kind: RuleKind, priority: i32, infer: F)
+        where F: FnMut(&Context<T>) -> Option<Inference<T>> + Clone + Send + 'static
+    {
+        self.push(name, kind, priority, Box::new(WithContext(infer)));
+    }
+}
+
+impl<T, R: ?Sized> RuleSet<T, R> {
+    /// Returns the names of the rules in the order they are tried.
+    pub fn names(&self) -> Vec<&str> {
+        self.rules.iter().map(|rule| &*rule.name).collect()
+    }
+
+    fn push(&mut self, name: &str, kind: RuleKind, priority: i32, rules: Box<R>) {
+        self.rules.push(NamedRule {name: name.into(), kind, priority, rules});
+        // The sort is stable, so rules with the same priority keep their order.
+        self.rules.sort_by_key(|rule| (rule.kind, Reverse(rule.priority)));
+        self.fired = None;
+    }
+}
+
+impl<T, R> Rules<T> for RuleSet<T, R>
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized
+{
+    fn infer(&mut self, cache: &HashSet<T>, facts: &[T]) -> Option<Inference<T>> {
+        infer_from_facts(self, cache, facts)
+    }
+
+    fn infer_with(&mut self, ctx: &Context<T>) -> Option<Inference<T>> {
+        self.fired = None;
+        for (i, rule) in self.rules.iter_mut().enumerate() {
+            if let Some(x) = rule.rules.infer_with(ctx) {
+                self.fired = Some(i);
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    fn fired_rule(&self) -> Option<&str> {
+        self.fired.map(|i| &*self.rules[i].name)
+    }
+}
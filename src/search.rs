@@ -0,0 +1,249 @@
+//! Solving with multiple histories.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+use super::{
+    contains_goal, same_state, split, state_hash, Database, Inference, Rules, Search, SolveOptions, SolveOutcome,
+};
+
+/// Solves the starting condition while exploring alternative inferences.
+///
+/// Every inference returned by `Rules::infer_all` starts a separate history.
+/// Histories are explored in the order given by `SolveOptions::search`.
+/// Every set of facts is explored once, also when histories reach it in different ways.
+/// A cycle is reported when an inference leads back to a set of facts
+/// from which the current one is reachable,
+/// such that a cycle is found even when histories enter it at different points.
+///
+/// Returns the outcome of every history without duplicates,
+/// e.g. every reachable minimum set of facts of a cycle.
+/// The number of steps in an outcome is the length of the first history that reached it.
+///
+/// The budget in `options` is shared by all histories,
+/// such that `SolveOptions::max_steps` limits the total number of inference steps.
+/// The search stops when the budget is exhausted.
+pub fn solve_all<T, R>(facts: Vec<T>, rules: &mut R, options: &SolveOptions<T>) -> Vec<SolveOutcome<T>>
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized
+{
+    search(facts, rules, options, |_, _| false).0
+}
+
+/// Solves like `solve_all`, but stops at the first outcome reaching `goal`.
+///
+/// The goal is a set of facts, use a slice of one element to prove a single fact.
+/// Only cycles and fixpoints can satisfy the goal.
+/// Like `prove`, a cycle reaches the goal when some set of facts in the cycle contains it.
+/// Returns `None` if no history reached the goal.
+pub fn solve_goal<T, R>(
+    facts: Vec<T>,
+    goal: &[T],
+    rules: &mut R,
+    options: &SolveOptions<T>
+) -> Option<SolveOutcome<T>>
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized
+{
+    let (mut outcomes, stopped) = search(facts, rules, options, |outcome, cycle| {
+        match *outcome {
+            SolveOutcome::Cycle(_) => cycle.iter().any(|state| contains_goal(state, goal)),
+            SolveOutcome::Fixpoint(ref facts) => contains_goal(facts, goal),
+            _ => false,
+        }
+    });
+    if stopped {outcomes.pop()} else {None}
+}
+
+// A set of facts in the search graph.
+struct Node<T> {
+    facts: Vec<T>,
+    // The number of inference steps from the starting condition when first reached.
+    depth: usize,
+    // Taken when the node is explored.
+    db: Option<Database<T>>,
+    // The nodes reached by one inference from this node.
+    edges: Vec<usize>,
+}
+
+// Returns the nodes on a path from `from` to `to`, using the edges found so far.
+fn path<T>(nodes: &[Node<T>], from: usize, to: usize) -> Option<Vec<usize>> {
+    // The node each visited node was reached from.
+    let mut prev: HashMap<usize, usize> = HashMap::new();
+    prev.insert(from, from);
+    let mut stack = vec![from];
+    while let Some(i) = stack.pop() {
+        if i == to {
+            let mut path = vec![to];
+            let mut j = to;
+            while j != from {
+                j = prev[&j];
+                path.push(j);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for &j in &nodes[i].edges {
+            if let Entry::Vacant(entry) = prev.entry(j) {
+                entry.insert(i);
+                stack.push(j);
+            }
+        }
+    }
+    None
+}
+
+// Nodes waiting to be explored.
+enum Frontier {
+    Stack(Vec<usize>),
+    Queue(VecDeque<usize>),
+    // Sorted such that the simplest set of facts is last,
+    // and among equally simple ones, the one found first.
+    Sorted(Vec<usize>),
+}
+
+impl Frontier {
+    fn push<T: Hash>(&mut self, node: usize, nodes: &[Node<T>], options: &SolveOptions<T>) {
+        match *self {
+            Frontier::Stack(ref mut stack) => stack.push(node),
+            Frontier::Queue(ref mut queue) => queue.push_back(node),
+            Frontier::Sorted(ref mut sorted) => {
+                let facts = &nodes[node].facts;
+                let k = sorted.partition_point(|&n| options.simpler(facts, &nodes[n].facts));
+                sorted.insert(k, node);
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        match *self {
+            Frontier::Stack(ref mut stack) => stack.pop(),
+            Frontier::Queue(ref mut queue) => queue.pop_front(),
+            Frontier::Sorted(ref mut sorted) => sorted.pop(),
+        }
+    }
+}
+
+// Explores histories until `stop` returns `true` for an outcome.
+// For a cycle, `stop` is called with every set of facts in the cycle.
+// Returns `true` if the search stopped, then the outcome that stopped it is the last one.
+fn search<T, R, F>(
+    facts: Vec<T>,
+    rules: &mut R,
+    options: &SolveOptions<T>,
+    mut stop: F
+) -> (Vec<SolveOutcome<T>>, bool)
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized, F: FnMut(&SolveOutcome<T>, &[&[T]]) -> bool
+{
+    let mut outcomes: Vec<SolveOutcome<T>> = vec![];
+    // Returns `true` when the search should stop.
+    let mut push = |outcome: SolveOutcome<T>, cycle: &[&[T]], outcomes: &mut Vec<SolveOutcome<T>>| {
+        let stop = stop(&outcome, cycle);
+        if let Some(k) = outcomes.iter().position(|n| n == &outcome) {
+            if !stop {return false};
+            // Move the outcome last, since it stopped the search.
+            outcomes.remove(k);
+        }
+        outcomes.push(outcome);
+        stop
+    };
+
+    if facts.iter().any(|n| options.is_contradiction(n)) {
+        return (vec![SolveOutcome::Contradiction {facts, steps: 0}], false);
+    }
+    let mut frontier = match options.search {
+        Search::DepthFirst => Frontier::Stack(vec![]),
+        Search::BreadthFirst => Frontier::Queue(VecDeque::new()),
+        Search::BestFirst => Frontier::Sorted(vec![]),
+    };
+    let mut steps = 0;
+    let mut stopped = false;
+    // Whether branches of splits survived.
+    let mut splits = HashMap::new();
+    // Nodes by the hash of their set of facts.
+    let mut index: HashMap<u64, Vec<usize>> = HashMap::new();
+    index.insert(state_hash(&facts, options.state_identity), vec![0]);
+    let mut nodes = vec![Node {facts: facts.clone(), depth: 0, db: Some(Database::new(facts)), edges: vec![]}];
+    frontier.push(0, &nodes, options);
+    while let Some(i) = frontier.pop() {
+        let db = nodes[i].db.take().unwrap();
+        let depth = nodes[i].depth;
+        if options.exhausted(steps, db.facts.len()) {
+            push(SolveOutcome::BudgetExhausted {facts: db.facts, steps}, &[], &mut outcomes);
+            break;
+        }
+
+        rules.reset(&db.facts);
+        let alternatives = rules.infer_all(&db.context());
+        if alternatives.is_empty() {
+            stopped = push(SolveOutcome::Fixpoint(db.facts), &[], &mut outcomes);
+            if stopped {break} else {continue};
+        }
+        let mut children = vec![];
+        for x in &alternatives {
+            if options.checked {
+                if let Err(err) = db.validate(x, depth) {
+                    stopped = push(SolveOutcome::Error(err), &[], &mut outcomes);
+                    if stopped {break} else {continue};
+                }
+            }
+            let mut child = db.clone();
+            // A split is a contradiction when no case survived.
+            let (dead, produced) = match *x {
+                Inference::Split {ref from, ref cases} => {
+                    match split(&db.facts, from, cases, rules, options, &mut splits) {
+                        Some(y) => (false, child.apply(&y).1),
+                        None => (true, vec![]),
+                    }
+                }
+                _ => (false, child.apply(x).1),
+            };
+            steps += 1;
+            if dead || produced.iter().any(|n| options.is_contradiction(n)) {
+                let outcome = SolveOutcome::Contradiction {facts: child.facts, steps: depth + 1};
+                stopped = push(outcome, &[], &mut outcomes);
+                if stopped {break} else {continue};
+            }
+
+            let hash = state_hash(&child.facts, options.state_identity);
+            let same = index.get(&hash).and_then(|bucket| {
+                bucket.iter().copied()
+                    .find(|&j| same_state(&nodes[j].facts, &child.facts, options.state_identity))
+            });
+            let j = match same {
+                Some(j) => j,
+                None => {
+                    // A new set of facts, which is explored later.
+                    let j = nodes.len();
+                    index.entry(hash).or_default().push(j);
+                    nodes.push(Node {facts: child.facts.clone(), depth: depth + 1, db: Some(child), edges: vec![]});
+                    nodes[i].edges.push(j);
+                    children.push(j);
+                    continue;
+                }
+            };
+            // The set of facts was reached before, which closes a cycle if it leads back here.
+            // Every cycle is found by the last of its inferences to be explored.
+            let cycle = path(&nodes, j, i);
+            if !nodes[i].edges.contains(&j) {nodes[i].edges.push(j)};
+            if let Some(cycle) = cycle {
+                // Find the first simplest set of facts in the cycle.
+                let mut min = &nodes[cycle[0]].facts;
+                for &k in &cycle {
+                    if options.simpler(&nodes[k].facts, min) {min = &nodes[k].facts}
+                }
+                let states: Vec<&[T]> = cycle.iter().map(|&k| &nodes[k].facts[..]).collect();
+                stopped = push(SolveOutcome::Cycle(min.clone()), &states, &mut outcomes);
+                if stopped {break};
+            }
+        }
+        if stopped {break};
+
+        // Push children in reverse order when searching depth first,
+        // such that the first alternative is explored first.
+        if let Frontier::Stack(_) = frontier {children.reverse()};
+        for j in children {
+            frontier.push(j, &nodes, options);
+        }
+    }
+    (outcomes, stopped)
+}
@@ -0,0 +1,196 @@
+//! Rules grouped under tactic names.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use super::{infer_from_facts, Context, Inference, Rules, SharedRules, SolveOutcome, Solver};
+
+/// Rules registered under tactic names, which can be enabled or disabled.
+///
+/// This replaces facts that only tell which rules should fire,
+/// e.g. `SortAll` or `RemoveRefl`, such that they do not appear in the result.
+///
+/// Enabled tactics are tried in the order they were added.
+/// The hooks `Rules::reset`, `Rules::added` and `Rules::removed`
+/// are called for every tactic, also disabled ones, such that indices stay up to date.
+/// `Rules::split` is called for every tactic in order,
+/// and `Rules::fired_rule` returns the name of the tactic that fired.
+///
+/// To use tactics with `solve_parallel`, create them with `Tactics::shared`.
+pub struct Tactics<T, R: ?Sized = dyn Rules<T>> {
+    tactics: Vec<Tactic<R>>,
+    // The index of the tactic that fired last.
+    fired: Option<usize>,
+    marker: PhantomData<T>,
+}
+
+struct Tactic<R: ?Sized> {
+    name: String,
+    enabled: bool,
+    rules: Vec<Box<R>>,
+}
+
+impl<T> Clone for Tactics<T, dyn SharedRules<T>> {
+    fn clone(&self) -> Self {
+        Tactics {
+            tactics: self.tactics.iter().map(|n| Tactic {
+                name: n.name.clone(),
+                enabled: n.enabled,
+                rules: n.rules.clone(),
+            }).collect(),
+            fired: self.fired,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, R: ?Sized> Default for Tactics<T, R> {
+    fn default() -> Tactics<T, R> {
+        Tactics {tactics: vec![], fired: None, marker: PhantomData}
+    }
+}
+
+impl<T> Tactics<T> {
+    /// Creates a new empty collection of tactics.
+    pub fn new() -> Tactics<T> {Tactics::default()}
+
+    /// Adds rules to a tactic.
+    ///
+    /// A new tactic is enabled.
+    /// Rules added to the same tactic are tried in the order they were added.
+    pub fn add<R: Rules<T> + 'static>(&mut self, tactic: &str, rules: R) {
+        self.push(tactic, Box::new(rules));
+    }
+}
+
+impl<T> Tactics<T, dyn SharedRules<T>> {
+    /// Creates a new empty collection of tactics that can be cloned for other threads.
+    ///
+    /// Rules added to it must be `Clone + Send`.
+    pub fn shared() -> Tactics<T, dyn SharedRules<T>> {Tactics::default()}
+
+    /// Adds rules to a tactic.
+    ///
+    /// A new tactic is enabled.
+    /// Rules added to the same tactic are tried in the order they were added.
+    pub fn add<R: SharedRules<T> + 'static>(&mut self, tactic: &str, rules: R) {
+        self.push(tactic, Box::new(rules));
+    }
+}
+
+impl<T, R: ?Sized> Tactics<T, R> {
+    /// Enables a tactic.
+    ///
+    /// Returns `false` if there is no tactic with this name.
+    pub fn enable(&mut self, tactic: &str) -> bool {self.set_enabled(tactic, true)}
+
+    /// Disables a tactic.
+    ///
+    /// Returns `false` if there is no tactic with this name.
+    pub fn disable(&mut self, tactic: &str) -> bool {self.set_enabled(tactic, false)}
+
+    /// Enables the listed tactics and disables the rest.
+    pub fn enable_only(&mut self, tactics: &[&str]) {
+        for n in &mut self.tactics {
+            n.enabled = tactics.contains(&&*n.name);
+        }
+    }
+
+    /// Returns `true` if the tactic is enabled.
+    pub fn is_enabled(&self, tactic: &str) -> bool {
+        self.tactics.iter().any(|n| n.name == tactic && n.enabled)
+    }
+
+    /// Returns the names of the tactics in the order they are tried.
+    pub fn names(&self) -> Vec<&str> {
+        self.tactics.iter().map(|n| &*n.name).collect()
+    }
+
+    fn push(&mut self, tactic: &str, rules: Box<R>) {
+        match self.tactics.iter_mut().find(|n| n.name == tactic) {
+            Some(n) => n.rules.push(rules),
+            None => self.tactics.push(Tactic {name: tactic.into(), enabled: true, rules: vec![rules]}),
+        }
+    }
+
+    fn set_enabled(&mut self, tactic: &str, enabled: bool) -> bool {
+        match self.tactics.iter_mut().find(|n| n.name == tactic) {
+            Some(n) => {
+                n.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<T: Clone + Eq + Hash, R: Rules<T> + ?Sized> Tactics<T, R> {
+    /// Solves in stages, where each stage enables only the listed tactics.
+    ///
+    /// E.g. `&[&["expand"], &["simplify", "check"]]` runs `expand` to fixpoint,
+    /// and then the simplifications.
+    /// Each stage runs until no rule applies or a cycle is reached,
+    /// with cycle detection starting over for every stage.
+    ///
+    /// Stops at the first stage that ends otherwise, e.g. in a contradiction.
+    /// Returns the outcome of the last stage that ran.
+    /// Limits in `SolveOptions` apply to the steps of all stages together.
+    /// The enabled tactics are restored afterwards.
+    pub fn sequence(&mut self, solver: &mut Solver<T>, stages: &[&[&str]]) -> SolveOutcome<T> {
+        let enabled: Vec<bool> = self.tactics.iter().map(|n| n.enabled).collect();
+        let outcome = solver.run_stages(stages, |solver, stage| {
+            self.enable_only(stage);
+            solver.run_rules(self)
+        });
+        for (n, enabled) in self.tactics.iter_mut().zip(enabled) {
+            n.enabled = enabled;
+        }
+        outcome
+    }
+}
+
+impl<T, R> Rules<T> for Tactics<T, R>
+    where T: Clone + Eq + Hash, R: Rules<T> + ?Sized
+{
+    fn infer(&mut self, cache: &HashSet<T>, facts: &[T]) -> Option<Inference<T>> {
+        infer_from_facts(self, cache, facts)
+    }
+
+    fn infer_with(&mut self, ctx: &Context<T>) -> Option<Inference<T>> {
+        self.fired = None;
+        for (i, tactic) in self.tactics.iter_mut().enumerate() {
+            if !tactic.enabled {continue};
+            for rules in &mut tactic.rules {
+                if let Some(x) = rules.infer_with(ctx) {
+                    self.fired = Some(i);
+                    return Some(x);
+                }
+            }
+        }
+        None
+    }
+
+    fn reset(&mut self, facts: &[T]) {
+        for rules in self.tactics.iter_mut().flat_map(|n| &mut n.rules) {rules.reset(facts)}
+    }
+
+    fn added(&mut self, fact: &T) {
+        for rules in self.tactics.iter_mut().flat_map(|n| &mut n.rules) {rules.added(fact)}
+    }
+
+    fn removed(&mut self, fact: &T) {
+        for rules in self.tactics.iter_mut().flat_map(|n| &mut n.rules) {rules.removed(fact)}
+    }
+
+    fn split(&mut self, from: &T, mut survivors: Vec<T>) -> Vec<T> {
+        for rules in self.tactics.iter_mut().flat_map(|n| &mut n.rules) {
+            survivors = rules.split(from, survivors);
+        }
+        survivors
+    }
+
+    fn fired_rule(&self) -> Option<&str> {
+        self.fired.map(|i| &*self.tactics[i].name)
+    }
+}